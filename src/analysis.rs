@@ -0,0 +1,881 @@
+//! Static analyses performed on a set of objects before applying them.
+
+use crate::{effective_namespace, object_action, Action};
+use k8s_openapi::api::core::v1::Namespace;
+use kube::{
+    api::{ApiResource, DynamicObject},
+    core::{GroupVersionKind, TypeMeta},
+    discovery::{self, Scope},
+    error::DiscoveryError,
+    Api, Client, Error as KubeError, ResourceExt,
+};
+use std::{
+    collections::{BTreeMap, HashMap, HashSet},
+    fmt,
+};
+use thiserror::Error;
+use tracing::{debug_span, instrument, warn, Instrument};
+
+/// Identifies the cluster object a manifest targets, regardless of the API
+/// version it is expressed in.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct ObjectIdentity {
+    pub group: String,
+    pub kind: String,
+    /// Unset for objects of a cluster-scoped kind.
+    pub namespace: Option<String>,
+    pub name: String,
+}
+
+impl ObjectIdentity {
+    /// Returns the identity of `object`, resolving its namespace like
+    /// [`apply_objects`](crate::apply_objects) does, unless its kind is
+    /// cluster-scoped according to `scopes`. Kinds missing from `scopes` are
+    /// assumed to be namespaced. Returns [`None`] if the object's `apiVersion`
+    /// cannot be parsed.
+    pub fn of(
+        object: &DynamicObject,
+        namespace: Option<&str>,
+        client: &Client,
+        scopes: &HashMap<GroupVersionKind, Scope>,
+    ) -> Option<Self> {
+        let gvk = GroupVersionKind::try_from(object.types.as_ref().unwrap_or(&TypeMeta::default()))
+            .ok()?;
+        let mut identity = Self {
+            group: gvk.group.clone(),
+            kind: gvk.kind.clone(),
+            namespace: Some(effective_namespace(object, namespace, client).to_string()),
+            name: object.name_any(),
+        };
+        if let Some(scope) = scopes.get(&gvk) {
+            identity.scope(scope);
+        }
+        Some(identity)
+    }
+
+    /// Drops the namespace if `scope` is the cluster.
+    pub(crate) fn scope(&mut self, scope: &Scope) {
+        if *scope == Scope::Cluster {
+            self.namespace = None;
+        }
+    }
+
+    /// Refers to the object the way `kubectl` does, e.g. `deployment.apps/foo`,
+    /// regardless of its namespace.
+    pub fn resource(&self) -> String {
+        let kind = self.kind.to_lowercase();
+        match self.group.as_str() {
+            "" => format!("{}/{}", kind, self.name),
+            g => format!("{}.{}/{}", kind, g, self.name),
+        }
+    }
+}
+
+impl fmt::Display for ObjectIdentity {
+    /// Formats the identity the way `kubectl` does, e.g. `deployment.apps/foo`
+    /// followed by the namespace, if any.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.resource())?;
+        match &self.namespace {
+            Some(ns) => write!(f, " (namespace {})", ns),
+            None => Ok(()),
+        }
+    }
+}
+
+#[derive(Error, Debug)]
+#[error("Duplicate object(s) in input")]
+pub struct DuplicateErrors(Vec<DuplicateError>);
+
+impl DuplicateErrors {
+    pub fn errors(&self) -> &[DuplicateError] {
+        &self.0
+    }
+}
+
+/// An object defined more than once. `first` and `second` are the positions
+/// (starting at 0) of both definitions in the input.
+#[derive(Error, Debug)]
+pub enum DuplicateError {
+    #[error("{identity} is defined twice with conflicting content (#{} and #{})", first + 1, second + 1)]
+    Conflicting {
+        identity: ObjectIdentity,
+        first: usize,
+        second: usize,
+    },
+
+    #[error("{identity} is defined twice (#{} and #{})", first + 1, second + 1)]
+    Identical {
+        identity: ObjectIdentity,
+        first: usize,
+        second: usize,
+    },
+}
+
+/// Detects objects that are defined more than once, which would otherwise
+/// result in concurrent requests whose outcome depends on timing.
+///
+/// Definitions that have the same effect (same content, or both deleting the
+/// object) are merged, keeping the first one, if `merge_identical` is set.
+/// All other duplicates are reported as errors. Objects are identified with
+/// the `scopes` of their kinds, see [`ObjectIdentity::of`].
+///
+/// Returns the objects kept along with their position in `objects`.
+pub fn check_duplicates(
+    objects: Vec<DynamicObject>,
+    namespace: Option<&str>,
+    client: &Client,
+    scopes: &HashMap<GroupVersionKind, Scope>,
+    merge_identical: bool,
+) -> Result<Vec<(usize, DynamicObject)>, DuplicateErrors> {
+    let mut seen: HashMap<ObjectIdentity, usize> = HashMap::new();
+    let mut errors = Vec::new();
+    let mut kept = vec![true; objects.len()];
+
+    for (i, object) in objects.iter().enumerate() {
+        let Some(identity) = ObjectIdentity::of(object, namespace, client, scopes) else {
+            continue;
+        };
+        let Some(&first) = seen.get(&identity) else {
+            seen.insert(identity, i);
+            continue;
+        };
+
+        if !same_effect(&objects[first], object) {
+            errors.push(DuplicateError::Conflicting {
+                identity,
+                first,
+                second: i,
+            });
+        } else if merge_identical {
+            kept[i] = false;
+        } else {
+            errors.push(DuplicateError::Identical {
+                identity,
+                first,
+                second: i,
+            });
+        }
+    }
+
+    if !errors.is_empty() {
+        return Err(DuplicateErrors(errors));
+    }
+
+    Ok(objects
+        .into_iter()
+        .enumerate()
+        .zip(kept)
+        .filter_map(|(o, k)| k.then_some(o))
+        .collect())
+}
+
+fn same_effect(a: &DynamicObject, b: &DynamicObject) -> bool {
+    match (object_action(a), object_action(b)) {
+        (Ok(Action::Delete), Ok(Action::Delete)) => true,
+        _ => a == b,
+    }
+}
+
+/// A kind that is neither served by the cluster nor defined by a
+/// CustomResourceDefinition of the input: objects of that kind, at the given
+/// positions (starting at 0) in the input, would be retried until timeout.
+#[derive(Error, Debug)]
+pub struct UnresolvableKind {
+    pub gvk: GroupVersionKind,
+    pub objects: Vec<usize>,
+}
+
+impl fmt::Display for UnresolvableKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let positions: Vec<_> = self.objects.iter().map(|i| format!("#{}", i + 1)).collect();
+        write!(
+            f,
+            "{} {} is neither served by the cluster nor defined in the input ({})",
+            self.gvk.api_version(),
+            self.gvk.kind,
+            positions.join(", ")
+        )
+    }
+}
+
+/// Kinds of a set of objects, as resolved by [`resolve_kinds`].
+#[derive(Debug, Default)]
+pub struct ResolvedKinds {
+    /// Scope of each kind served by the cluster or defined in the input.
+    pub scopes: HashMap<GroupVersionKind, Scope>,
+    /// Kinds that can't be resolved, in order of first appearance.
+    pub unresolvable: Vec<UnresolvableKind>,
+}
+
+/// Resolves the kinds of the objects to apply against the cluster discovery
+/// and the CustomResourceDefinitions applied along with them.
+///
+/// Objects to be deleted are ignored, since a missing kind means they are
+/// already gone. Kinds that can't be discovered for other reasons, e.g. the
+/// API server being overloaded, are left unresolved, as they may well be
+/// applied once retried.
+#[instrument(skip_all)]
+pub async fn resolve_kinds(objects: &[DynamicObject], client: &Client) -> ResolvedKinds {
+    let mut scopes: HashMap<_, _> = objects.iter().flat_map(defined_kinds).collect();
+
+    let mut positions: HashMap<GroupVersionKind, Vec<usize>> = HashMap::new();
+    for (i, object) in objects.iter().enumerate() {
+        if !matches!(object_action(object), Ok(Action::Apply)) {
+            continue;
+        }
+        let Ok(gvk) =
+            GroupVersionKind::try_from(object.types.as_ref().unwrap_or(&TypeMeta::default()))
+        else {
+            continue;
+        };
+        positions.entry(gvk).or_default().push(i);
+    }
+
+    let to_discover: Vec<_> = positions
+        .keys()
+        .filter(|gvk| !scopes.contains_key(gvk))
+        .cloned()
+        .collect();
+    let discovered = futures::future::join_all(to_discover.into_iter().map(|gvk| async move {
+        let resp = discovery::pinned_kind(client, &gvk)
+            .instrument(debug_span!("discover_api_resource").or_current())
+            .await;
+        (gvk, resp)
+    }))
+    .await;
+
+    let mut unresolvable = Vec::new();
+    for (gvk, resp) in discovered {
+        match resp {
+            Ok((_, capabilities)) => {
+                scopes.insert(gvk, capabilities.scope);
+            }
+            Err(KubeError::Discovery(
+                DiscoveryError::MissingKind(_)
+                | DiscoveryError::MissingApiGroup(_)
+                | DiscoveryError::MissingResource(_)
+                | DiscoveryError::EmptyApiGroup(_),
+            )) => {
+                let objects = positions.remove(&gvk).unwrap_or_default();
+                unresolvable.push(UnresolvableKind { gvk, objects });
+            }
+            Err(KubeError::Api(e)) if e.code == 404 => {
+                let objects = positions.remove(&gvk).unwrap_or_default();
+                unresolvable.push(UnresolvableKind { gvk, objects });
+            }
+            Err(e) => {
+                warn!(
+                    api_version = gvk.api_version(),
+                    kind = gvk.kind,
+                    error = %e,
+                    "Cannot discover kind"
+                );
+            }
+        }
+    }
+    unresolvable.sort_by_key(|k| k.objects.first().copied());
+
+    ResolvedKinds {
+        scopes,
+        unresolvable,
+    }
+}
+
+/// Returns the kinds defined by `object` if it is a CustomResourceDefinition
+/// to be applied, along with their scope.
+fn defined_kinds(object: &DynamicObject) -> Vec<(GroupVersionKind, Scope)> {
+    let is_crd = object.types.as_ref().is_some_and(|t| {
+        t.api_version.starts_with("apiextensions.k8s.io/") && t.kind == "CustomResourceDefinition"
+    });
+    if !is_crd || !matches!(object_action(object), Ok(Action::Apply)) {
+        return Vec::new();
+    }
+
+    let spec = &object.data["spec"];
+    let (Some(group), Some(kind)) = (spec["group"].as_str(), spec["names"]["kind"].as_str()) else {
+        return Vec::new();
+    };
+    let scope = match spec["scope"].as_str() {
+        Some("Cluster") => Scope::Cluster,
+        _ => Scope::Namespaced,
+    };
+
+    let versions = spec["versions"]
+        .as_array()
+        .into_iter()
+        .flatten()
+        .filter(|v| v["served"].as_bool().unwrap_or(true))
+        .filter_map(|v| v["name"].as_str())
+        .chain(spec["version"].as_str());
+
+    versions
+        .map(|v| (GroupVersionKind::gvk(group, v, kind), scope.clone()))
+        .collect()
+}
+
+/// A namespace that neither exists in the cluster nor is created by the input:
+/// objects in that namespace, at the given positions (starting at 0) in the
+/// input, would be retried until timeout.
+#[derive(Error, Debug)]
+pub struct UnreachableNamespace {
+    pub namespace: String,
+    pub objects: Vec<usize>,
+}
+
+impl UnreachableNamespace {
+    /// Returns a Namespace object that, once added to the input, makes the
+    /// namespace reachable.
+    pub fn to_object(&self) -> DynamicObject {
+        DynamicObject::new(&self.namespace, &ApiResource::erase::<Namespace>(&()))
+    }
+}
+
+impl fmt::Display for UnreachableNamespace {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let positions: Vec<_> = self.objects.iter().map(|i| format!("#{}", i + 1)).collect();
+        write!(
+            f,
+            "namespace {} neither exists nor is defined in the input ({})",
+            self.namespace,
+            positions.join(", ")
+        )
+    }
+}
+
+/// Finds the namespaces that objects to apply target, resolved like
+/// [`apply_objects`](crate::apply_objects) does, but that neither exist in the
+/// cluster nor are created by a Namespace object of the input.
+///
+/// Only objects of a namespaced kind, according to `kinds`, are considered.
+/// Namespaces that can't be checked, e.g. for lack of permission with a
+/// namespace-scoped service account or as the API server is overloaded, are
+/// assumed to exist.
+#[instrument(skip_all)]
+pub async fn find_unreachable_namespaces(
+    objects: &[DynamicObject],
+    namespace: Option<&str>,
+    client: &Client,
+    kinds: &ResolvedKinds,
+) -> Vec<UnreachableNamespace> {
+    let created: HashSet<_> = objects
+        .iter()
+        .filter(|o| {
+            o.types
+                .as_ref()
+                .is_some_and(|t| t.api_version == "v1" && t.kind == "Namespace")
+        })
+        .filter(|o| matches!(object_action(o), Ok(Action::Apply)))
+        .map(|o| o.name_any())
+        .collect();
+
+    let mut targeted: BTreeMap<&str, Vec<usize>> = BTreeMap::new();
+    for (i, object) in objects.iter().enumerate() {
+        if !matches!(object_action(object), Ok(Action::Apply)) {
+            continue;
+        }
+        let Ok(gvk) =
+            GroupVersionKind::try_from(object.types.as_ref().unwrap_or(&TypeMeta::default()))
+        else {
+            continue;
+        };
+        if kinds.scopes.get(&gvk) != Some(&Scope::Namespaced) {
+            continue;
+        }
+        let ns = effective_namespace(object, namespace, client);
+        if !created.contains(ns) {
+            targeted.entry(ns).or_default().push(i);
+        }
+    }
+
+    let api: Api<Namespace> = Api::all(client.clone());
+    let existing = futures::future::join_all(targeted.keys().map(|ns| {
+        api.get_opt(ns)
+            .instrument(debug_span!("get_namespace", namespace = ns).or_current())
+    }))
+    .await;
+
+    let mut unreachable = Vec::new();
+    for ((ns, objects), resp) in targeted.into_iter().zip(existing) {
+        match resp {
+            Ok(Some(_)) => {}
+            Ok(None) => unreachable.push(UnreachableNamespace {
+                namespace: ns.to_string(),
+                objects,
+            }),
+            Err(e) => {
+                warn!(namespace = ns, error = %e, "Cannot check whether namespace exists");
+            }
+        }
+    }
+
+    unreachable
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{mock::with_mock_service, ANNOTATION_ACTION};
+    use http::{Request, Response, StatusCode};
+    use kube::client::Body;
+    use serde_json::{json, Value};
+
+    fn api_resources(group_version: &str, resources: Value) -> Response<Body> {
+        let list = json!({
+            "kind": "APIResourceList",
+            "groupVersion": group_version,
+            "resources": resources,
+        });
+        Response::builder()
+            .body(Body::from(serde_json::to_vec(&list).unwrap()))
+            .unwrap()
+    }
+
+    fn core_resources() -> Response<Body> {
+        api_resources(
+            "v1",
+            json!([{
+                "name": "pods",
+                "singularName": "pod",
+                "namespaced": true,
+                "kind": "Pod",
+                "verbs": ["delete", "get", "patch"],
+            }]),
+        )
+    }
+
+    fn not_found() -> Response<Body> {
+        let status = json!({
+            "kind": "Status",
+            "apiVersion": "v1",
+            "metadata": {},
+            "status": "Failure",
+            "message": "the server could not find the requested resource",
+            "reason": "NotFound",
+            "details": {},
+            "code": 404
+        });
+        Response::builder()
+            .status(StatusCode::NOT_FOUND)
+            .body(Body::from(serde_json::to_vec(&status).unwrap()))
+            .unwrap()
+    }
+
+    fn widget(api_version: &str) -> Value {
+        json!({
+            "apiVersion": api_version,
+            "kind": "Widget",
+            "metadata": { "name": "example" },
+        })
+    }
+
+    fn widget_crd() -> Value {
+        json!({
+            "apiVersion": "apiextensions.k8s.io/v1",
+            "kind": "CustomResourceDefinition",
+            "metadata": { "name": "widgets.example.com" },
+            "spec": {
+                "group": "example.com",
+                "names": { "kind": "Widget", "plural": "widgets" },
+                "scope": "Cluster",
+                "versions": [
+                    { "name": "v1", "served": true, "storage": true },
+                    { "name": "v1alpha1", "served": false, "storage": false },
+                ],
+            }
+        })
+    }
+
+    fn pod(namespace: Option<&str>, image: &str) -> Value {
+        let mut pod = json!({
+            "apiVersion": "v1",
+            "kind": "Pod",
+            "metadata": { "name": "example" },
+            "spec": {
+                "containers": [{ "name": "example", "image": image }],
+            }
+        });
+        if let Some(ns) = namespace {
+            pod["metadata"]["namespace"] = json!(ns);
+        }
+        pod
+    }
+
+    fn objects(values: Vec<Value>) -> Vec<DynamicObject> {
+        values
+            .into_iter()
+            .map(|v| serde_json::from_value(v).unwrap())
+            .collect()
+    }
+
+    fn client() -> Client {
+        let (service, _) = tower_test::mock::pair::<Request<Body>, Response<Body>>();
+        Client::new(service, "default")
+    }
+
+    #[tokio::test]
+    async fn distinct_objects_are_kept() {
+        let input = objects(vec![pod(None, "a"), pod(Some("other"), "a")]);
+        let output =
+            check_duplicates(input.clone(), None, &client(), &HashMap::new(), false).unwrap();
+        assert_eq!(output, input.into_iter().enumerate().collect::<Vec<_>>());
+    }
+
+    #[tokio::test]
+    async fn defaulted_namespaces_are_resolved() {
+        let input = objects(vec![pod(None, "a"), pod(Some("test_ns"), "b")]);
+        let errors =
+            check_duplicates(input, Some("test_ns"), &client(), &HashMap::new(), true).unwrap_err();
+        assert!(matches!(
+            errors.errors(),
+            [DuplicateError::Conflicting {
+                first: 0,
+                second: 1,
+                ..
+            }]
+        ));
+    }
+
+    #[tokio::test]
+    async fn identical_duplicates_are_reported() {
+        let input = objects(vec![pod(None, "a"), pod(None, "a")]);
+        let errors = check_duplicates(input, None, &client(), &HashMap::new(), false).unwrap_err();
+        assert!(matches!(
+            errors.errors(),
+            [DuplicateError::Identical {
+                first: 0,
+                second: 1,
+                ..
+            }]
+        ));
+    }
+
+    #[tokio::test]
+    async fn identical_duplicates_are_merged() {
+        let input = objects(vec![
+            pod(None, "a"),
+            pod(Some("default"), "b"),
+            pod(None, "a"),
+        ]);
+        let errors =
+            check_duplicates(input.clone(), None, &client(), &HashMap::new(), true).unwrap_err();
+        assert_eq!(errors.errors().len(), 1);
+
+        let input = objects(vec![
+            pod(None, "a"),
+            pod(None, "a"),
+            pod(Some("other"), "b"),
+        ]);
+        let output =
+            check_duplicates(input.clone(), None, &client(), &HashMap::new(), true).unwrap();
+        assert_eq!(output, [(0, input[0].clone()), (2, input[2].clone())]);
+    }
+
+    #[tokio::test]
+    async fn cluster_scoped_objects_have_no_namespace() {
+        let namespace = |ns: Option<&str>| {
+            let mut object = json!({
+                "apiVersion": "v1",
+                "kind": "Namespace",
+                "metadata": { "name": "example" },
+            });
+            if let Some(ns) = ns {
+                object["metadata"]["namespace"] = json!(ns);
+            }
+            object
+        };
+        let scopes =
+            HashMap::from([(GroupVersionKind::gvk("", "v1", "Namespace"), Scope::Cluster)]);
+        let input = objects(vec![namespace(None), namespace(Some("other"))]);
+
+        let identity = ObjectIdentity::of(&input[0], None, &client(), &scopes).unwrap();
+        assert_eq!(identity.namespace, None);
+        assert_eq!(identity.to_string(), "namespace/example");
+
+        let errors = check_duplicates(input, None, &client(), &scopes, false).unwrap_err();
+        assert_eq!(
+            errors.errors()[0].to_string(),
+            "namespace/example is defined twice with conflicting content (#1 and #2)"
+        );
+    }
+
+    #[tokio::test]
+    async fn apply_and_delete_conflict() {
+        let mut deleted = pod(None, "a");
+        deleted["metadata"]["annotations"][ANNOTATION_ACTION] = json!(Action::Delete.as_ref());
+        let input = objects(vec![pod(None, "a"), deleted.clone()]);
+        let errors = check_duplicates(input, None, &client(), &HashMap::new(), true).unwrap_err();
+        assert!(matches!(
+            errors.errors(),
+            [DuplicateError::Conflicting { .. }]
+        ));
+
+        let mut other_deleted = pod(None, "b");
+        other_deleted["metadata"]["annotations"][ANNOTATION_ACTION] =
+            json!(Action::Delete.as_ref());
+        let input = objects(vec![deleted, other_deleted]);
+        let output = check_duplicates(input, None, &client(), &HashMap::new(), true).unwrap();
+        assert_eq!(output.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn kinds_served_by_the_cluster_are_resolved() {
+        let expectations = vec![(
+            Request::get("/api/v1").body(Body::empty()).unwrap(),
+            core_resources(),
+        )];
+
+        with_mock_service(expectations, |s| async {
+            let input = objects(vec![pod(None, "a"), pod(Some("other"), "b")]);
+            let kinds = resolve_kinds(&input, &Client::new(s, "default")).await;
+            assert!(kinds.unresolvable.is_empty());
+            assert_eq!(
+                kinds.scopes.get(&GroupVersionKind::gvk("", "v1", "Pod")),
+                Some(&Scope::Namespaced)
+            );
+        })
+        .await;
+    }
+
+    #[tokio::test]
+    async fn missing_kinds_are_unresolvable() {
+        let expectations = vec![
+            (
+                Request::get("/api/v1").body(Body::empty()).unwrap(),
+                api_resources("v1", json!([])),
+            ),
+            (
+                Request::get("/apis/example.com/v1")
+                    .body(Body::empty())
+                    .unwrap(),
+                not_found(),
+            ),
+        ];
+
+        with_mock_service(expectations, |s| async {
+            let input = objects(vec![widget("example.com/v1"), pod(None, "a")]);
+            let kinds = resolve_kinds(&input, &Client::new(s, "default")).await;
+            assert!(kinds.scopes.is_empty());
+            assert!(matches!(
+                kinds.unresolvable.as_slice(),
+                [
+                    UnresolvableKind { gvk: w, objects: wo },
+                    UnresolvableKind { gvk: p, objects: po },
+                ] if w.kind == "Widget" && wo == &[0] && p.kind == "Pod" && po == &[1]
+            ));
+        })
+        .await;
+    }
+
+    #[tokio::test]
+    async fn kinds_failing_discovery_are_left_unresolved() {
+        let status = json!({
+            "kind": "Status",
+            "apiVersion": "v1",
+            "metadata": {},
+            "status": "Failure",
+            "message": "the server is currently unable to handle the request",
+            "reason": "ServiceUnavailable",
+            "details": {},
+            "code": 503
+        });
+        let expectations = vec![(
+            Request::get("/apis/example.com/v1")
+                .body(Body::empty())
+                .unwrap(),
+            Response::builder()
+                .status(StatusCode::SERVICE_UNAVAILABLE)
+                .body(Body::from(serde_json::to_vec(&status).unwrap()))
+                .unwrap(),
+        )];
+
+        with_mock_service(expectations, |s| async {
+            let input = objects(vec![widget("example.com/v1")]);
+            let kinds = resolve_kinds(&input, &Client::new(s, "default")).await;
+            assert!(kinds.scopes.is_empty());
+            assert!(kinds.unresolvable.is_empty());
+        })
+        .await;
+    }
+
+    #[tokio::test]
+    async fn kinds_defined_in_input_are_resolved() {
+        let expectations = vec![(
+            Request::get("/apis/apiextensions.k8s.io/v1")
+                .body(Body::empty())
+                .unwrap(),
+            api_resources(
+                "apiextensions.k8s.io/v1",
+                json!([{
+                    "name": "customresourcedefinitions",
+                    "singularName": "customresourcedefinition",
+                    "namespaced": false,
+                    "kind": "CustomResourceDefinition",
+                    "verbs": ["delete", "get", "patch"],
+                }]),
+            ),
+        )];
+
+        with_mock_service(expectations, |s| async {
+            let input = objects(vec![widget("example.com/v1"), widget_crd()]);
+            let kinds = resolve_kinds(&input, &Client::new(s, "default")).await;
+            assert!(kinds.unresolvable.is_empty());
+            assert_eq!(
+                kinds
+                    .scopes
+                    .get(&GroupVersionKind::gvk("example.com", "v1", "Widget")),
+                Some(&Scope::Cluster)
+            );
+            assert!(!kinds.scopes.contains_key(&GroupVersionKind::gvk(
+                "example.com",
+                "v1alpha1",
+                "Widget"
+            )));
+        })
+        .await;
+    }
+
+    #[tokio::test]
+    async fn kinds_of_deleted_objects_are_ignored() {
+        let mut deleted = widget("example.com/v1");
+        deleted["metadata"]["annotations"][ANNOTATION_ACTION] = json!(Action::Delete.as_ref());
+
+        with_mock_service(vec![], |s| async {
+            let input = objects(vec![deleted]);
+            let kinds = resolve_kinds(&input, &Client::new(s, "default")).await;
+            assert!(kinds.scopes.is_empty());
+            assert!(kinds.unresolvable.is_empty());
+        })
+        .await;
+    }
+
+    fn namespace(name: &str) -> Value {
+        json!({
+            "apiVersion": "v1",
+            "kind": "Namespace",
+            "metadata": { "name": name },
+        })
+    }
+
+    fn pod_kind() -> ResolvedKinds {
+        ResolvedKinds {
+            scopes: HashMap::from([(GroupVersionKind::gvk("", "v1", "Pod"), Scope::Namespaced)]),
+            ..Default::default()
+        }
+    }
+
+    #[tokio::test]
+    async fn existing_namespaces_are_reachable() {
+        let expectations = vec![
+            (
+                Request::get("/api/v1/namespaces/default")
+                    .body(Body::empty())
+                    .unwrap(),
+                Response::builder()
+                    .body(Body::from(
+                        serde_json::to_vec(&namespace("default")).unwrap(),
+                    ))
+                    .unwrap(),
+            ),
+            (
+                Request::get("/api/v1/namespaces/test_ns")
+                    .body(Body::empty())
+                    .unwrap(),
+                Response::builder()
+                    .body(Body::from(
+                        serde_json::to_vec(&namespace("test_ns")).unwrap(),
+                    ))
+                    .unwrap(),
+            ),
+        ];
+
+        with_mock_service(expectations, |s| async {
+            let input = objects(vec![pod(Some("default"), "a"), pod(None, "b")]);
+            let unreachable = find_unreachable_namespaces(
+                &input,
+                Some("test_ns"),
+                &Client::new(s, "default"),
+                &pod_kind(),
+            )
+            .await;
+            assert!(unreachable.is_empty());
+        })
+        .await;
+    }
+
+    #[tokio::test]
+    async fn missing_namespaces_are_unreachable() {
+        let expectations = vec![(
+            Request::get("/api/v1/namespaces/default")
+                .body(Body::empty())
+                .unwrap(),
+            not_found(),
+        )];
+
+        with_mock_service(expectations, |s| async {
+            let input = objects(vec![pod(None, "a"), widget("example.com/v1")]);
+            let unreachable =
+                find_unreachable_namespaces(&input, None, &Client::new(s, "default"), &pod_kind())
+                    .await;
+            assert!(matches!(
+                unreachable.as_slice(),
+                [UnreachableNamespace { namespace, objects }] if namespace == "default" && objects == &[0]
+            ));
+            assert_eq!(unreachable[0].to_object().name_any(), "default");
+        })
+        .await;
+    }
+
+    #[tokio::test]
+    async fn unchecked_namespaces_are_assumed_reachable() {
+        for (code, reason) in [
+            (StatusCode::FORBIDDEN, "Forbidden"),
+            (StatusCode::TOO_MANY_REQUESTS, "TooManyRequests"),
+        ] {
+            let status = json!({
+                "kind": "Status",
+                "apiVersion": "v1",
+                "metadata": {},
+                "status": "Failure",
+                "message": "cannot get namespace \"default\"",
+                "reason": reason,
+                "details": {},
+                "code": code.as_u16()
+            });
+            let expectations = vec![(
+                Request::get("/api/v1/namespaces/default")
+                    .body(Body::empty())
+                    .unwrap(),
+                Response::builder()
+                    .status(code)
+                    .body(Body::from(serde_json::to_vec(&status).unwrap()))
+                    .unwrap(),
+            )];
+
+            with_mock_service(expectations, |s| async {
+                let input = objects(vec![pod(None, "a")]);
+                let unreachable = find_unreachable_namespaces(
+                    &input,
+                    None,
+                    &Client::new(s, "default"),
+                    &pod_kind(),
+                )
+                .await;
+                assert!(unreachable.is_empty());
+            })
+            .await;
+        }
+    }
+
+    #[tokio::test]
+    async fn namespaces_defined_in_input_are_reachable() {
+        with_mock_service(vec![], |s| async {
+            let input = objects(vec![pod(Some("test_ns"), "a"), namespace("test_ns")]);
+            let unreachable =
+                find_unreachable_namespaces(&input, None, &Client::new(s, "default"), &pod_kind())
+                    .await;
+            assert!(unreachable.is_empty());
+        })
+        .await;
+    }
+}
@@ -1,5 +1,10 @@
+use crate::{deserialize_duration, deserialize_optional_duration, FailureCause};
 use ::backoff as backoffcrate;
+use rand::Rng;
+use serde::Deserialize;
 use std::time::Duration;
+use strum_macros::{AsRefStr, EnumString};
+use thiserror::Error;
 
 /// Provides a local backoff trait that mirrors the external [`Backoff`][1] one.
 /// All [`Backoff`][1] implementations can be used interchangeably with this trait.
@@ -8,6 +13,32 @@ use std::time::Duration;
 pub trait Backoff {
     fn reset(&mut self) {}
     fn next_backoff(&mut self) -> Option<Duration>;
+
+    /// Returns how long to wait before retrying after the `attempt`-th attempt
+    /// (starting at 1) failed because of `cause`, or `None` to give up.
+    ///
+    /// This is what [`apply_objects`](crate::apply_objects) calls, so that
+    /// strategies can tell e.g. a missing CustomResourceDefinition from
+    /// throttling. Defaults to [`Backoff::next_backoff`], ignoring both.
+    fn next_backoff_after(&mut self, cause: FailureCause, attempt: usize) -> Option<Duration> {
+        let _ = (cause, attempt);
+        self.next_backoff()
+    }
+
+    /// Returns how long to wait before retrying after the `attempt`-th attempt
+    /// was throttled by the API server, which asked to wait `retry_after`, or
+    /// `None` to give up.
+    ///
+    /// Defaults to waiting exactly as asked, rather than backing off on top,
+    /// as long as [`Backoff::next_backoff_after`] does not give up.
+    fn next_backoff_throttled(
+        &mut self,
+        attempt: usize,
+        retry_after: Duration,
+    ) -> Option<Duration> {
+        self.next_backoff_after(FailureCause::Throttled, attempt)
+            .map(|_| retry_after)
+    }
 }
 
 impl<T> Backoff for T
@@ -23,18 +54,578 @@ where
     }
 }
 
-#[derive(Clone)]
-pub(crate) struct BackoffWrapper<T: Backoff>(pub T);
+/// Waits the same interval between all attempts.
+#[derive(Clone, Debug)]
+pub struct Constant {
+    pub interval: Duration,
+}
+
+impl Backoff for Constant {
+    fn next_backoff(&mut self) -> Option<Duration> {
+        Some(self.interval)
+    }
+}
+
+/// Waits a random interval between `base` and three times the previous one,
+/// capped at `max`, as described in [Exponential Backoff And Jitter][1]. This
+/// spreads out retries of many objects failing at the same time better than
+/// an exponential backoff does.
+///
+/// [1]: https://aws.amazon.com/blogs/architecture/exponential-backoff-and-jitter/
+#[derive(Clone, Debug)]
+pub struct DecorrelatedJitter {
+    base: Duration,
+    max: Duration,
+    previous: Duration,
+}
+
+impl DecorrelatedJitter {
+    pub fn new(base: Duration, max: Duration) -> Self {
+        Self {
+            base,
+            max,
+            previous: base,
+        }
+    }
+}
+
+impl Backoff for DecorrelatedJitter {
+    fn reset(&mut self) {
+        self.previous = self.base;
+    }
+
+    fn next_backoff(&mut self) -> Option<Duration> {
+        let upper = (self.previous * 3).max(self.base);
+        let next = rand::thread_rng()
+            .gen_range(self.base..=upper)
+            .min(self.max);
+        self.previous = next;
+        Some(next)
+    }
+}
+
+/// Waits intervals following the Fibonacci sequence, i.e. `initial`,
+/// `initial`, 2 × `initial`, 3 × `initial`, 5 × `initial`..., capped at `max`.
+/// Grows slower than an exponential backoff.
+#[derive(Clone, Debug)]
+pub struct Fibonacci {
+    initial: Duration,
+    max: Duration,
+    current: Duration,
+    next: Duration,
+}
+
+impl Fibonacci {
+    pub fn new(initial: Duration, max: Duration) -> Self {
+        Self {
+            initial,
+            max,
+            current: initial,
+            next: initial,
+        }
+    }
+}
+
+impl Backoff for Fibonacci {
+    fn reset(&mut self) {
+        self.current = self.initial;
+        self.next = self.initial;
+    }
+
+    fn next_backoff(&mut self) -> Option<Duration> {
+        let interval = self.current.min(self.max);
+        (self.current, self.next) = (self.next, self.current.saturating_add(self.next));
+        Some(interval)
+    }
+}
+
+/// Randomizes the intervals of another backoff by up to `factor` times their
+/// value in either direction, e.g. a factor of 0.5 turns 10s into anything
+/// between 5s and 15s.
+#[derive(Clone, Debug)]
+pub struct Jittered<B> {
+    pub inner: B,
+    pub factor: f64,
+}
+
+impl<B: Backoff> Backoff for Jittered<B> {
+    fn reset(&mut self) {
+        self.inner.reset()
+    }
+
+    fn next_backoff(&mut self) -> Option<Duration> {
+        let interval = self.inner.next_backoff()?;
+        Some(self.randomize(interval))
+    }
+
+    fn next_backoff_after(&mut self, cause: FailureCause, attempt: usize) -> Option<Duration> {
+        let interval = self.inner.next_backoff_after(cause, attempt)?;
+        Some(self.randomize(interval))
+    }
+
+    /// Not randomized, as the API server asked for this delay.
+    fn next_backoff_throttled(
+        &mut self,
+        attempt: usize,
+        retry_after: Duration,
+    ) -> Option<Duration> {
+        self.inner.next_backoff_throttled(attempt, retry_after)
+    }
+}
+
+impl<B> Jittered<B> {
+    fn randomize(&self, interval: Duration) -> Duration {
+        let factor = self.factor.clamp(0.0, 1.0);
+        if factor == 0.0 || factor.is_nan() {
+            return interval;
+        }
+        interval.mul_f64(rand::thread_rng().gen_range(1.0 - factor..=1.0 + factor))
+    }
+}
+
+/// Gives up once `max` attempts were made, whatever another backoff says.
+#[derive(Clone, Debug)]
+pub struct MaxAttempts<B> {
+    inner: B,
+    max: usize,
+    attempts: usize,
+}
+
+impl<B> MaxAttempts<B> {
+    pub fn new(inner: B, max: usize) -> Self {
+        Self {
+            inner,
+            max,
+            attempts: 0,
+        }
+    }
+}
+
+impl<B: Backoff> Backoff for MaxAttempts<B> {
+    fn reset(&mut self) {
+        self.attempts = 0;
+        self.inner.reset()
+    }
+
+    fn next_backoff(&mut self) -> Option<Duration> {
+        self.attempts += 1;
+        if self.attempts >= self.max {
+            return None;
+        }
+        self.inner.next_backoff()
+    }
+
+    fn next_backoff_after(&mut self, cause: FailureCause, attempt: usize) -> Option<Duration> {
+        self.attempts = attempt;
+        if self.attempts >= self.max {
+            return None;
+        }
+        self.inner.next_backoff_after(cause, attempt)
+    }
+
+    fn next_backoff_throttled(
+        &mut self,
+        attempt: usize,
+        retry_after: Duration,
+    ) -> Option<Duration> {
+        self.attempts = attempt;
+        if self.attempts >= self.max {
+            return None;
+        }
+        self.inner.next_backoff_throttled(attempt, retry_after)
+    }
+}
+
+/// Kind of backoff built by a [`RetryPolicy`].
+#[derive(Clone, Copy, Debug, Default, PartialEq, Deserialize, EnumString, AsRefStr)]
+#[serde(rename_all = "kebab-case")]
+#[strum(serialize_all = "kebab-case")]
+pub enum Strategy {
+    #[default]
+    Exponential,
+    Constant,
+    DecorrelatedJitter,
+    Fibonacci,
+}
+
+/// Describes how to retry objects, e.g. as read from a configuration file:
+///
+/// ```yaml
+/// strategy: exponential
+/// initial: 400ms
+/// maxInterval: 30s
+/// multiplier: 5
+/// jitter: 0.5
+/// maxAttempts: 10
+/// ```
+#[derive(Clone, Debug, PartialEq, Deserialize)]
+#[serde(default, rename_all = "camelCase", deny_unknown_fields)]
+pub struct RetryPolicy {
+    pub strategy: Strategy,
+    /// Interval after the first attempt, and the only one of the constant
+    /// strategy.
+    #[serde(deserialize_with = "deserialize_duration")]
+    pub initial: Duration,
+    /// Intervals never exceed this.
+    #[serde(deserialize_with = "deserialize_duration")]
+    pub max_interval: Duration,
+    /// Growth of intervals from one attempt to the next with the exponential
+    /// strategy.
+    pub multiplier: f64,
+    /// Randomization of intervals, from 0 (none) to 1. Ignored by the
+    /// decorrelated jitter strategy, which is random by design.
+    #[serde(deserialize_with = "deserialize_jitter")]
+    pub jitter: f64,
+    /// Give up on an object after this many attempts. Retries indefinitely
+    /// if unset.
+    pub max_attempts: Option<usize>,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            strategy: Strategy::default(),
+            initial: Duration::from_millis(400),
+            max_interval: Duration::from_secs(30),
+            multiplier: 5.0,
+            jitter: 0.5,
+            max_attempts: None,
+        }
+    }
+}
 
-impl<T> backoffcrate::backoff::Backoff for BackoffWrapper<T>
+#[derive(Error, Debug)]
+#[error("Invalid jitter: {0:?}, expected a number from 0 to 1")]
+pub struct ParseJitterError(String);
+
+/// Parses a randomization factor of intervals, from 0 to 1.
+pub fn parse_jitter(s: &str) -> Result<f64, ParseJitterError> {
+    s.parse()
+        .ok()
+        .filter(|j| (0.0..=1.0).contains(j))
+        .ok_or_else(|| ParseJitterError(s.to_string()))
+}
+
+/// Deserializes a randomization factor of intervals, from 0 to 1.
+fn deserialize_jitter<'de, D>(deserializer: D) -> Result<f64, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    let jitter = f64::deserialize(deserializer)?;
+    if !(0.0..=1.0).contains(&jitter) {
+        return Err(serde::de::Error::custom(ParseJitterError(
+            jitter.to_string(),
+        )));
+    }
+    Ok(jitter)
+}
+
+/// Deserializes an optional randomization factor of intervals, from 0 to 1.
+fn deserialize_optional_jitter<'de, D>(deserializer: D) -> Result<Option<f64>, D::Error>
 where
-    T: Backoff,
+    D: serde::Deserializer<'de>,
 {
+    deserialize_jitter(deserializer).map(Some)
+}
+
+/// A [`RetryPolicy`] of which only some fields are set, e.g. as read from a
+/// [`KindOverride`](crate::overrides::KindOverride), the others being
+/// inherited from another policy.
+#[derive(Clone, Debug, Default, PartialEq, Deserialize)]
+#[serde(default, rename_all = "camelCase", deny_unknown_fields)]
+pub struct RetryPolicyPatch {
+    pub strategy: Option<Strategy>,
+    #[serde(deserialize_with = "deserialize_optional_duration")]
+    pub initial: Option<Duration>,
+    #[serde(deserialize_with = "deserialize_optional_duration")]
+    pub max_interval: Option<Duration>,
+    pub multiplier: Option<f64>,
+    #[serde(deserialize_with = "deserialize_optional_jitter")]
+    pub jitter: Option<f64>,
+    pub max_attempts: Option<usize>,
+}
+
+impl RetryPolicyPatch {
+    /// Returns `base` with the fields set in this patch replaced.
+    pub fn apply(&self, base: &RetryPolicy) -> RetryPolicy {
+        RetryPolicy {
+            strategy: self.strategy.unwrap_or(base.strategy),
+            initial: self.initial.unwrap_or(base.initial),
+            max_interval: self.max_interval.unwrap_or(base.max_interval),
+            multiplier: self.multiplier.unwrap_or(base.multiplier),
+            jitter: self.jitter.unwrap_or(base.jitter),
+            max_attempts: self.max_attempts.or(base.max_attempts),
+        }
+    }
+}
+
+impl RetryPolicy {
+    pub fn build(&self) -> PolicyBackoff {
+        let strategy = match self.strategy {
+            Strategy::Exponential => StrategyBackoff::Exponential(
+                backoffcrate::ExponentialBackoffBuilder::new()
+                    .with_initial_interval(self.initial)
+                    .with_randomization_factor(self.jitter.clamp(0.0, 1.0))
+                    .with_multiplier(self.multiplier)
+                    .with_max_interval(self.max_interval)
+                    .with_max_elapsed_time(None)
+                    .build(),
+            ),
+            Strategy::Constant => StrategyBackoff::Constant(Jittered {
+                inner: Constant {
+                    interval: self.initial,
+                },
+                factor: self.jitter,
+            }),
+            Strategy::DecorrelatedJitter => StrategyBackoff::DecorrelatedJitter(
+                DecorrelatedJitter::new(self.initial, self.max_interval),
+            ),
+            Strategy::Fibonacci => StrategyBackoff::Fibonacci(Jittered {
+                inner: Fibonacci::new(self.initial, self.max_interval),
+                factor: self.jitter,
+            }),
+        };
+
+        PolicyBackoff(MaxAttempts::new(
+            strategy,
+            self.max_attempts.unwrap_or(usize::MAX),
+        ))
+    }
+}
+
+/// Backoff built from a [`RetryPolicy`].
+#[derive(Clone, Debug)]
+pub struct PolicyBackoff(MaxAttempts<StrategyBackoff>);
+
+impl Backoff for PolicyBackoff {
+    fn reset(&mut self) {
+        self.0.reset()
+    }
+
     fn next_backoff(&mut self) -> Option<Duration> {
         self.0.next_backoff()
     }
 
+    fn next_backoff_after(&mut self, cause: FailureCause, attempt: usize) -> Option<Duration> {
+        self.0.next_backoff_after(cause, attempt)
+    }
+    fn next_backoff_throttled(
+        &mut self,
+        attempt: usize,
+        retry_after: Duration,
+    ) -> Option<Duration> {
+        self.0.next_backoff_throttled(attempt, retry_after)
+    }
+}
+
+/// Either of two backoffs, e.g. the default one or an override.
+#[derive(Clone, Debug)]
+pub(crate) enum Either<L, R> {
+    Left(L),
+    Right(R),
+}
+
+impl<L: Backoff, R: Backoff> Backoff for Either<L, R> {
     fn reset(&mut self) {
-        self.0.reset()
+        match self {
+            Self::Left(b) => b.reset(),
+            Self::Right(b) => b.reset(),
+        }
+    }
+
+    fn next_backoff(&mut self) -> Option<Duration> {
+        match self {
+            Self::Left(b) => b.next_backoff(),
+            Self::Right(b) => b.next_backoff(),
+        }
+    }
+
+    fn next_backoff_after(&mut self, cause: FailureCause, attempt: usize) -> Option<Duration> {
+        match self {
+            Self::Left(b) => b.next_backoff_after(cause, attempt),
+            Self::Right(b) => b.next_backoff_after(cause, attempt),
+        }
+    }
+    fn next_backoff_throttled(
+        &mut self,
+        attempt: usize,
+        retry_after: Duration,
+    ) -> Option<Duration> {
+        match self {
+            Self::Left(b) => b.next_backoff_throttled(attempt, retry_after),
+            Self::Right(b) => b.next_backoff_throttled(attempt, retry_after),
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+enum StrategyBackoff {
+    Exponential(backoffcrate::ExponentialBackoff),
+    Constant(Jittered<Constant>),
+    DecorrelatedJitter(DecorrelatedJitter),
+    Fibonacci(Jittered<Fibonacci>),
+}
+
+impl StrategyBackoff {
+    fn inner(&mut self) -> &mut dyn Backoff {
+        match self {
+            Self::Exponential(b) => b,
+            Self::Constant(b) => b,
+            Self::DecorrelatedJitter(b) => b,
+            Self::Fibonacci(b) => b,
+        }
+    }
+}
+
+impl Backoff for StrategyBackoff {
+    fn reset(&mut self) {
+        self.inner().reset()
+    }
+
+    fn next_backoff(&mut self) -> Option<Duration> {
+        self.inner().next_backoff()
+    }
+
+    fn next_backoff_after(&mut self, cause: FailureCause, attempt: usize) -> Option<Duration> {
+        self.inner().next_backoff_after(cause, attempt)
+    }
+    fn next_backoff_throttled(
+        &mut self,
+        attempt: usize,
+        retry_after: Duration,
+    ) -> Option<Duration> {
+        self.inner().next_backoff_throttled(attempt, retry_after)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn intervals(b: &mut impl Backoff, count: usize) -> Vec<Option<Duration>> {
+        (0..count).map(|_| b.next_backoff()).collect()
+    }
+
+    fn secs(s: &[u64]) -> Vec<Option<Duration>> {
+        s.iter().map(|s| Some(Duration::from_secs(*s))).collect()
+    }
+
+    #[test]
+    fn fibonacci_intervals() {
+        let mut b = Fibonacci::new(Duration::from_secs(1), Duration::from_secs(10));
+        assert_eq!(intervals(&mut b, 7), secs(&[1, 1, 2, 3, 5, 8, 10]));
+        b.reset();
+        assert_eq!(intervals(&mut b, 3), secs(&[1, 1, 2]));
+    }
+
+    #[test]
+    fn decorrelated_jitter_intervals_are_bounded() {
+        let (base, max) = (Duration::from_secs(1), Duration::from_secs(10));
+        let mut b = DecorrelatedJitter::new(base, max);
+        for i in intervals(&mut b, 100) {
+            assert!((base..=max).contains(&i.unwrap()));
+        }
+    }
+
+    #[test]
+    fn jitter_stays_within_factor() {
+        let mut b = Jittered {
+            inner: Constant {
+                interval: Duration::from_secs(10),
+            },
+            factor: 0.5,
+        };
+        for i in intervals(&mut b, 100) {
+            assert!((Duration::from_secs(5)..=Duration::from_secs(15)).contains(&i.unwrap()));
+        }
+    }
+
+    #[test]
+    fn max_attempts_is_effective() {
+        let mut b = MaxAttempts::new(
+            Constant {
+                interval: Duration::from_secs(1),
+            },
+            3,
+        );
+        assert_eq!(intervals(&mut b, 3), [secs(&[1, 1]), vec![None]].concat());
+        b.reset();
+        assert_eq!(b.next_backoff_after(FailureCause::Other, 1), secs(&[1])[0]);
+        assert_eq!(b.next_backoff_after(FailureCause::Other, 3), None);
+    }
+
+    #[test]
+    fn throttled_waits_as_asked() {
+        let mut b = MaxAttempts::new(
+            Constant {
+                interval: Duration::from_secs(1),
+            },
+            3,
+        );
+        let retry_after = Duration::from_secs(5);
+        assert_eq!(b.next_backoff_throttled(1, retry_after), Some(retry_after));
+        assert_eq!(b.next_backoff_throttled(3, retry_after), None);
+    }
+
+    #[test]
+    fn patch_retry_policy() {
+        let base = RetryPolicy {
+            strategy: Strategy::Fibonacci,
+            max_attempts: Some(10),
+            ..Default::default()
+        };
+        let patch: RetryPolicyPatch = serde_yaml::from_str("initial: 5s\nmaxAttempts: 3").unwrap();
+        assert_eq!(
+            patch.apply(&base),
+            RetryPolicy {
+                strategy: Strategy::Fibonacci,
+                initial: Duration::from_secs(5),
+                max_attempts: Some(3),
+                ..Default::default()
+            }
+        );
+    }
+
+    #[test]
+    fn deserialize_retry_policy() {
+        let policy: RetryPolicy = serde_yaml::from_str(
+            "strategy: fibonacci\ninitial: 1s\nmaxInterval: 1m\njitter: 0\nmaxAttempts: 3",
+        )
+        .unwrap();
+        assert_eq!(
+            policy,
+            RetryPolicy {
+                strategy: Strategy::Fibonacci,
+                initial: Duration::from_secs(1),
+                max_interval: Duration::from_secs(60),
+                jitter: 0.0,
+                max_attempts: Some(3),
+                ..Default::default()
+            }
+        );
+        assert_eq!(
+            intervals(&mut policy.build(), 3),
+            [secs(&[1, 1]), vec![None]].concat()
+        );
+        assert!(serde_yaml::from_str::<RetryPolicy>("initial: 5d").is_err());
+    }
+
+    #[test]
+    fn reject_jitter_out_of_range() {
+        assert_eq!(parse_jitter("0.3").unwrap(), 0.3);
+        for jitter in ["NaN", "-0.1", "1.5", "half"] {
+            assert!(parse_jitter(jitter).is_err(), "{jitter}");
+        }
+        for jitter in [".nan", "-0.1", "1.5"] {
+            let yaml = format!("jitter: {jitter}");
+            assert!(
+                serde_yaml::from_str::<RetryPolicy>(&yaml).is_err(),
+                "{yaml}"
+            );
+            assert!(
+                serde_yaml::from_str::<RetryPolicyPatch>(&yaml).is_err(),
+                "{yaml}"
+            );
+        }
     }
 }
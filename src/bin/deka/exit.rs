@@ -0,0 +1,161 @@
+//! Exit codes telling wrapper scripts what went wrong, without having to
+//! parse the output.
+
+use deka::{
+    report::{ObjectReport, Report},
+    ApplyError, FailureCause,
+};
+use std::fmt;
+
+/// Any other error, e.g. failing to write a report.
+pub const ERROR: u8 = 1;
+/// The input is invalid, e.g. a manifest cannot be parsed or an object can
+/// never be applied. Also used by clap for invalid arguments.
+pub const INVALID_INPUT: u8 = 2;
+/// The cluster could not be reached, or refused the credentials.
+pub const UNREACHABLE: u8 = 3;
+/// Some objects were given up on.
+pub const OBJECTS_FAILED: u8 = 4;
+/// The `--timeout` was reached before all objects were applied.
+pub const TIMEOUT: u8 = 5;
+/// Objects differ from the cluster. Reserved for a diff mode, so that
+/// wrapper scripts can rely on it once there is one.
+#[allow(dead_code)]
+pub const DIFF_DETECTED: u8 = 6;
+/// Cancelled by a signal, following the shell convention for SIGINT.
+pub const CANCELLED: u8 = 130;
+
+/// An error ending a command with a specific exit code.
+pub struct Failure {
+    pub code: u8,
+    pub report: miette::Report,
+}
+
+impl fmt::Display for Failure {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.report.fmt(f)
+    }
+}
+
+impl From<miette::Report> for Failure {
+    fn from(report: miette::Report) -> Self {
+        Self {
+            code: ERROR,
+            report,
+        }
+    }
+}
+
+pub trait WithExitCode<T> {
+    /// Ends the command with `code` in case of error.
+    fn exit_code(self, code: u8) -> Result<T, Failure>;
+}
+
+impl<T> WithExitCode<T> for miette::Result<T> {
+    fn exit_code(self, code: u8) -> Result<T, Failure> {
+        self.map_err(|report| Failure { code, report })
+    }
+}
+
+/// Returns the exit code of an apply that gave up on some objects: the
+/// cluster is deemed unreachable if none of them ever got a response other
+/// than a refusal of the credentials.
+pub fn of_failed(report: &Report) -> u8 {
+    let failed: Vec<_> = report
+        .objects
+        .iter()
+        .filter(|o| o.error.is_some())
+        .collect();
+    if !failed.is_empty() && failed.iter().all(|o| never_reached(o)) {
+        UNREACHABLE
+    } else if report
+        .errors()
+        .any(|e| matches!(e, ApplyError::DeadlineExceeded { .. }))
+    {
+        TIMEOUT
+    } else {
+        OBJECTS_FAILED
+    }
+}
+
+fn never_reached(o: &ObjectReport) -> bool {
+    !o.failures.is_empty()
+        && o.failures.iter().all(|f| {
+            matches!(
+                f.cause,
+                FailureCause::Unavailable | FailureCause::Unauthorized
+            )
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use deka::report::FailedAttempt;
+    use std::time::Duration;
+
+    fn failures(causes: &[FailureCause]) -> Vec<FailedAttempt> {
+        causes
+            .iter()
+            .enumerate()
+            .map(|(i, cause)| FailedAttempt {
+                attempt: i + 1,
+                elapsed: Duration::ZERO,
+                cause: *cause,
+                error: "boom".to_string(),
+            })
+            .collect()
+    }
+
+    fn deadline_exceeded() -> Option<ApplyError> {
+        Some(ApplyError::DeadlineExceeded {
+            attempts: 1,
+            last_error: "boom".to_string(),
+        })
+    }
+
+    #[test]
+    fn classify_failed_applies() {
+        let unreachable = Report {
+            objects: vec![
+                ObjectReport {
+                    failures: failures(&[FailureCause::Unavailable]),
+                    error: deadline_exceeded(),
+                    ..Default::default()
+                },
+                ObjectReport::default(),
+            ],
+        };
+        assert_eq!(of_failed(&unreachable), UNREACHABLE);
+
+        let unauthorized = Report {
+            objects: vec![ObjectReport {
+                failures: failures(&[FailureCause::Unauthorized, FailureCause::Unavailable]),
+                error: deadline_exceeded(),
+                ..Default::default()
+            }],
+        };
+        assert_eq!(of_failed(&unauthorized), UNREACHABLE);
+
+        let timeout = Report {
+            objects: vec![ObjectReport {
+                failures: failures(&[FailureCause::Unavailable, FailureCause::MissingKind]),
+                error: deadline_exceeded(),
+                ..Default::default()
+            }],
+        };
+        assert_eq!(of_failed(&timeout), TIMEOUT);
+
+        let failed = Report {
+            objects: vec![ObjectReport {
+                failures: failures(&[FailureCause::Rejected]),
+                error: Some(ApplyError::TimedOut {
+                    attempts: 1,
+                    last_error: "boom".to_string(),
+                }),
+                ..Default::default()
+            }],
+        };
+        assert_eq!(of_failed(&failed), OBJECTS_FAILED);
+    }
+}
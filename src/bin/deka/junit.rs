@@ -0,0 +1,210 @@
+//! JUnit XML report, which CI systems display like test results.
+
+use crate::summary::{outcome, seconds};
+use deka::{
+    redact::Redactor,
+    report::{ObjectReport, Report},
+};
+use std::{fmt::Write, time::Duration};
+
+/// Formats a report with one testcase per object, classified by kind and
+/// namespace. Objects given up on have a failure with their final error, and
+/// the history of their failed attempts. Sensitive values are masked before
+/// escaping, as they would no longer match once escaped.
+pub fn format(report: &Report, elapsed: Duration, redactor: &Redactor) -> String {
+    let tests = report.objects.len();
+    let failures = report.errors().count();
+    let time = elapsed.as_secs_f64();
+
+    let mut out = String::from("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n");
+    writeln!(
+        out,
+        "<testsuites name=\"deka\" tests=\"{tests}\" failures=\"{failures}\" time=\"{time:.3}\">"
+    )
+    .unwrap();
+    writeln!(
+        out,
+        "  <testsuite name=\"apply\" tests=\"{tests}\" failures=\"{failures}\" time=\"{time:.3}\">"
+    )
+    .unwrap();
+    for o in &report.objects {
+        testcase(&mut out, o, redactor);
+    }
+    out.push_str("  </testsuite>\n</testsuites>\n");
+    out
+}
+
+fn testcase(out: &mut String, o: &ObjectReport, redactor: &Redactor) {
+    let text = |s: &str| escape(&redactor.redact_text(s));
+    let (classname, name) = match &o.identity {
+        Some(i) => match &i.namespace {
+            Some(ns) => (format!("{}/{}", i.kind, ns), i.name.as_str()),
+            None => (i.kind.clone(), i.name.as_str()),
+        },
+        None => (String::new(), o.label.as_str()),
+    };
+    write!(
+        out,
+        "    <testcase classname=\"{}\" name=\"{}\" time=\"{:.3}\"",
+        text(&classname),
+        text(name),
+        o.duration.as_secs_f64()
+    )
+    .unwrap();
+
+    let Some(error) = &o.error else {
+        out.push_str("/>\n");
+        return;
+    };
+    let history: Vec<_> = o
+        .failures
+        .iter()
+        .map(|f| {
+            format!(
+                "attempt {} at {}, {}: {}",
+                f.attempt,
+                seconds(f.elapsed),
+                f.cause.as_ref(),
+                f.error
+            )
+        })
+        .collect();
+    writeln!(
+        out,
+        ">\n      <failure message=\"{}\" type=\"{}\">{}</failure>\n    </testcase>",
+        text(&error.to_string()),
+        outcome(o),
+        text(&history.join("\n"))
+    )
+    .unwrap();
+}
+
+/// Escapes text for use in XML attributes and elements, dropping the
+/// characters XML 1.0 does not allow, e.g. terminal escape sequences.
+fn escape(s: &str) -> String {
+    let mut escaped = String::with_capacity(s.len());
+    for c in s.chars().filter(|c| is_xml_char(*c)) {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&apos;"),
+            '\n' => escaped.push_str("&#10;"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+/// Whether XML 1.0 allows `c`, surrogates aside as they are no `char`.
+fn is_xml_char(c: char) -> bool {
+    matches!(c, '\t' | '\n' | '\r' | '\u{20}'..='\u{FFFD}' | '\u{10000}'..)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use deka::{analysis::ObjectIdentity, report::FailedAttempt, ApplyError, FailureCause};
+
+    fn identity(kind: &str, namespace: Option<&str>, name: &str) -> Option<ObjectIdentity> {
+        Some(ObjectIdentity {
+            group: String::new(),
+            kind: kind.to_string(),
+            namespace: namespace.map(str::to_string),
+            name: name.to_string(),
+        })
+    }
+
+    #[test]
+    fn classify_cluster_scoped_objects_by_kind() {
+        let namespace = ObjectReport {
+            identity: identity("Namespace", None, "example"),
+            duration: Duration::from_secs(1),
+            ..Default::default()
+        };
+        let mut out = String::new();
+        testcase(&mut out, &namespace, &Redactor::default());
+        assert_eq!(
+            out,
+            "    <testcase classname=\"Namespace\" name=\"example\" time=\"1.000\"/>\n"
+        );
+    }
+
+    #[test]
+    fn redact_before_escaping() {
+        let redactor = Redactor::new(false);
+        redactor.learn_values([serde_json::json!({
+            "apiVersion": "v1",
+            "kind": "Secret",
+            "stringData": { "password": "pa\"ss&word1" },
+        })]);
+        let failed = ObjectReport {
+            identity: identity("Pod", Some("default"), "example"),
+            failures: vec![FailedAttempt {
+                attempt: 1,
+                elapsed: Duration::ZERO,
+                cause: FailureCause::Rejected,
+                error: "invalid password pa\"ss&word1".to_string(),
+            }],
+            error: Some(ApplyError::TimedOut {
+                attempts: 2,
+                last_error: "invalid password pa\"ss&word1".to_string(),
+            }),
+            ..Default::default()
+        };
+        let mut out = String::new();
+        testcase(&mut out, &failed, &redactor);
+        assert!(!out.contains("word1"), "{out}");
+        assert!(out.contains("last error: invalid password ***"), "{out}");
+    }
+
+    #[test]
+    fn drop_characters_invalid_in_xml() {
+        assert_eq!(
+            escape("\u{1b}[31mred\u{1b}[0m\u{0}\tok\u{FFFE}"),
+            "[31mred[0m\tok"
+        );
+    }
+
+    #[test]
+    fn one_testcase_per_object() {
+        let report = Report {
+            objects: vec![
+                ObjectReport {
+                    identity: identity("Pod", Some("default"), "a"),
+                    duration: Duration::from_secs(1),
+                    ..Default::default()
+                },
+                ObjectReport {
+                    identity: identity("Pod", Some("default"), "b"),
+                    failures: vec![FailedAttempt {
+                        attempt: 1,
+                        elapsed: Duration::from_millis(300),
+                        cause: FailureCause::MissingNamespace,
+                        error: "namespaces \"default\" not found".to_string(),
+                    }],
+                    duration: Duration::from_secs(1),
+                    error: Some(ApplyError::TimedOut {
+                        attempts: 2,
+                        last_error: "<boom>".to_string(),
+                    }),
+                    ..Default::default()
+                },
+            ],
+        };
+        assert_eq!(
+            format(&report, Duration::from_secs(2), &Redactor::default()),
+            r#"<?xml version="1.0" encoding="UTF-8"?>
+<testsuites name="deka" tests="2" failures="1" time="2.000">
+  <testsuite name="apply" tests="2" failures="1" time="2.000">
+    <testcase classname="Pod/default" name="a" time="1.000"/>
+    <testcase classname="Pod/default" name="b" time="1.000">
+      <failure message="Timed out after 2 attempt(s), last error: &lt;boom&gt;" type="timed-out">attempt 1 at 0.3s, missing-namespace: namespaces &quot;default&quot; not found</failure>
+    </testcase>
+  </testsuite>
+</testsuites>
+"#
+        );
+    }
+}
@@ -0,0 +1,306 @@
+//! Live view of the progress of an apply, redrawn in place on interactive
+//! terminals.
+
+use deka::{
+    redact::Redactor,
+    report::{Event, EventKind},
+    FailureCause,
+};
+use std::{
+    fmt::Write as _,
+    io::{self, Write},
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+use terminal_size::{terminal_size_of, Width};
+use tokio::{
+    sync::mpsc::{self, UnboundedSender},
+    task::JoinHandle,
+};
+use tracing_subscriber::fmt::{writer::BoxMakeWriter, MakeWriter};
+use unicode_width::UnicodeWidthChar;
+
+/// How often the view is redrawn.
+const REFRESH_INTERVAL: Duration = Duration::from_millis(150);
+
+/// Number of failing objects listed, the others are only counted.
+const MAX_FAILING: usize = 10;
+
+/// Errors are cut to this many characters to keep to one line.
+const MAX_ERROR_LEN: usize = 100;
+
+/// Width assumed when that of the terminal is unknown.
+const DEFAULT_WIDTH: usize = 80;
+
+#[derive(Clone, Debug, PartialEq)]
+enum Status {
+    Pending,
+    Retrying {
+        label: String,
+        cause: FailureCause,
+        error: String,
+    },
+    Done,
+    Failed,
+}
+
+/// What is known of each object so far.
+struct View(Vec<Status>);
+
+impl View {
+    /// Records `event`, masking sensitive values in its error while it is
+    /// whole, before it is cut to fit.
+    fn update(&mut self, event: Event, redactor: &Redactor) {
+        self.0[event.index] = match event.kind {
+            EventKind::AttemptFailed { cause, error } => Status::Retrying {
+                label: event.label,
+                cause,
+                error: redactor.redact_text(&error).into_owned(),
+            },
+            EventKind::Succeeded => Status::Done,
+            EventKind::GaveUp { .. } => Status::Failed,
+        };
+    }
+
+    fn render(&self) -> String {
+        let count = |f: fn(&Status) -> bool| self.0.iter().filter(|s| f(s)).count();
+        let mut out = format!(
+            "{} pending, {} retrying, {} done, {} failed\n",
+            count(|s| *s == Status::Pending),
+            count(|s| matches!(s, Status::Retrying { .. })),
+            count(|s| *s == Status::Done),
+            count(|s| *s == Status::Failed),
+        );
+
+        let failing: Vec<_> = self
+            .0
+            .iter()
+            .filter_map(|s| match s {
+                Status::Retrying {
+                    label,
+                    cause,
+                    error,
+                } => Some((label, cause, error)),
+                _ => None,
+            })
+            .collect();
+        for (label, cause, error) in failing.iter().take(MAX_FAILING) {
+            writeln!(out, "  {} {}: {}", label, cause.as_ref(), one_line(error)).unwrap();
+        }
+        if failing.len() > MAX_FAILING {
+            writeln!(out, "  and {} more", failing.len() - MAX_FAILING).unwrap();
+        }
+        out
+    }
+}
+
+/// Keeps the first line of `error`, cut to [`MAX_ERROR_LEN`] characters.
+fn one_line(error: &str) -> String {
+    let line = error.lines().next().unwrap_or_default();
+    match line.char_indices().nth(MAX_ERROR_LEN) {
+        Some((i, _)) => format!("{}…", &line[..i]),
+        None => line.to_string(),
+    }
+}
+
+/// Cuts each line of `out` to `width` columns, so that none wraps.
+fn fit(out: &str, width: usize) -> String {
+    let mut fitted = String::with_capacity(out.len());
+    for line in out.lines() {
+        let mut used = 0;
+        let mut chars = line.chars().peekable();
+        while let Some(c) = chars.next() {
+            let w = c.width().unwrap_or(0);
+            // Keep a column for the ellipsis unless this is the last char.
+            let reserved = usize::from(chars.peek().is_some());
+            if used + w + reserved > width {
+                if width > 0 {
+                    fitted.push('…');
+                }
+                break;
+            }
+            used += w;
+            fitted.push(c);
+        }
+        fitted.push('\n');
+    }
+    fitted
+}
+
+/// Displays the progress of `count` objects on stderr until the returned
+/// sender and its clones are dropped, at which point the view is erased.
+pub fn spawn(
+    count: usize,
+    redactor: Redactor,
+    screen: Screen,
+) -> (UnboundedSender<Event>, JoinHandle<()>) {
+    let (tx, mut rx) = mpsc::unbounded_channel();
+    let task = tokio::spawn(async move {
+        let mut view = View(vec![Status::Pending; count]);
+        let mut refresh = tokio::time::interval(REFRESH_INTERVAL);
+        loop {
+            tokio::select! {
+                event = rx.recv() => match event {
+                    Some(e) => view.update(e, &redactor),
+                    None => break,
+                },
+                _ = refresh.tick() => screen.draw(&view.render()),
+            }
+        }
+        screen.draw("");
+    });
+    (tx, task)
+}
+
+/// The view as last drawn on stderr, shared with the writers of logs so that
+/// logs are written above it rather than over it.
+#[derive(Clone, Debug, Default)]
+pub struct Screen(Arc<Mutex<String>>);
+
+impl Screen {
+    /// Replaces the view with `out`, cut to the width of the terminal.
+    fn draw(&self, out: &str) {
+        let width = terminal_size_of(io::stderr())
+            .map(|(Width(w), _)| w as usize)
+            .unwrap_or(DEFAULT_WIDTH);
+        let out = fit(out, width);
+        let mut drawn = self.0.lock().unwrap();
+        let mut stderr = io::stderr().lock();
+        let _ = write!(stderr, "{}{}", erase(&drawn), out);
+        let _ = stderr.flush();
+        *drawn = out;
+    }
+
+    /// Makes writers of logs displayed above the view.
+    pub fn make_writer(&self, inner: BoxMakeWriter) -> ScreenMakeWriter {
+        ScreenMakeWriter {
+            inner,
+            screen: self.clone(),
+        }
+    }
+}
+
+/// Moves the cursor back to the start of `drawn` and erases it.
+fn erase(drawn: &str) -> String {
+    match drawn.lines().count() {
+        0 => "\x1b[J".to_string(),
+        n => format!("\x1b[{}F\x1b[J", n),
+    }
+}
+
+/// Makes writers erasing the view before each log and drawing it back after.
+pub struct ScreenMakeWriter {
+    inner: BoxMakeWriter,
+    screen: Screen,
+}
+
+impl<'a> MakeWriter<'a> for ScreenMakeWriter {
+    type Writer = ScreenWriter<'a, Box<dyn Write + 'a>>;
+
+    fn make_writer(&'a self) -> Self::Writer {
+        ScreenWriter {
+            inner: self.inner.make_writer(),
+            screen: &self.screen,
+        }
+    }
+}
+
+/// Writes each buffer, which holds a whole event as formatted by
+/// `tracing_subscriber`, above the view.
+pub struct ScreenWriter<'a, W> {
+    inner: W,
+    screen: &'a Screen,
+}
+
+impl<W: Write> Write for ScreenWriter<'_, W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let drawn = self.screen.0.lock().unwrap();
+        let mut stderr = io::stderr().lock();
+        let _ = write!(stderr, "{}", erase(&drawn));
+        let _ = stderr.flush();
+        self.inner.write_all(buf)?;
+        self.inner.flush()?;
+        let _ = write!(stderr, "{}", drawn);
+        let _ = stderr.flush();
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn event(index: usize, kind: EventKind) -> Event {
+        Event {
+            index,
+            label: format!("pod/{index}"),
+            kind,
+        }
+    }
+
+    #[test]
+    fn fit_lines_to_width() {
+        assert_eq!(fit("short\nmuch longer\n", 6), "short\nmuch …\n");
+        assert_eq!(fit("exact\n", 5), "exact\n");
+        assert_eq!(fit("日本語\n", 4), "日…\n");
+    }
+
+    #[test]
+    fn count_objects_and_list_failing_ones() {
+        let redactor = Redactor::default();
+        let mut view = View(vec![Status::Pending; 4]);
+        view.update(
+            event(
+                0,
+                EventKind::AttemptFailed {
+                    cause: FailureCause::MissingNamespace,
+                    error: "namespaces \"a\" not found\nmore".to_string(),
+                },
+            ),
+            &redactor,
+        );
+        view.update(event(1, EventKind::Succeeded), &redactor);
+        view.update(
+            event(
+                2,
+                EventKind::GaveUp {
+                    error: "boom".to_string(),
+                },
+            ),
+            &redactor,
+        );
+        assert_eq!(
+            view.render(),
+            "1 pending, 1 retrying, 1 done, 1 failed
+  pod/0 missing-namespace: namespaces \"a\" not found
+"
+        );
+    }
+
+    #[test]
+    fn redact_errors_before_cutting_them() {
+        let redactor = Redactor::new(false);
+        redactor.learn_values([serde_json::json!({
+            "apiVersion": "v1",
+            "kind": "Secret",
+            "stringData": { "token": "s3cr3t-t0ken" },
+        })]);
+        let mut view = View(vec![Status::Pending]);
+        let error = format!("{} s3cr3t-t0ken", "x".repeat(MAX_ERROR_LEN - 6));
+        view.update(
+            event(
+                0,
+                EventKind::AttemptFailed {
+                    cause: FailureCause::Other,
+                    error,
+                },
+            ),
+            &redactor,
+        );
+        assert!(view.render().ends_with(" ***\n"), "{}", view.render());
+    }
+}
@@ -1,19 +1,58 @@
-use backoff::ExponentialBackoffBuilder;
+mod exit;
+mod junit;
+mod live;
+mod metrics;
+mod redact;
+mod result_file;
+mod summary;
+mod telemetry;
+
 use clap::{Args, Parser, Subcommand};
 use clap_verbosity_flag::{InfoLevel, Verbosity};
+use deka::{
+    analysis::{self, ResolvedKinds, UnreachableNamespace},
+    backoff::{RetryPolicy, Strategy},
+    concurrency::AdaptiveConcurrencyLayer,
+    metrics::Metrics,
+    overrides::KindOverride,
+    rate_limit::RateLimitLayer,
+    redact::{Redactor, SensitiveFields},
+    throttling::RetryAfterLayer,
+    ApplyParams,
+};
+use exit::{Failure, WithExitCode};
+use http::header::{HeaderName, HeaderValue, USER_AGENT};
 use kube::{
     api::DynamicObject,
     client::ClientBuilder,
     config::{KubeConfigOptions, Kubeconfig},
     Client, Config,
 };
-use miette::{IntoDiagnostic, Result};
+use miette::{miette, IntoDiagnostic, Result};
+use opentelemetry::trace::TracerProvider;
+use opentelemetry_sdk::trace::SdkTracerProvider;
+use prometheus_client::registry::Registry;
+use redact::RedactingMakeWriter;
 use serde::Deserialize;
 use serde_yaml::Deserializer;
-use std::{fs::File, io, path::PathBuf, time::Duration};
-use tower;
+use std::{
+    fmt::Display,
+    fs::File,
+    io::{self, IsTerminal, Write},
+    net::SocketAddr,
+    path::{Path, PathBuf},
+    process::ExitCode,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+use telemetry::OtlpProtocol;
+use tokio_util::sync::CancellationToken;
 use tracing::level_filters::LevelFilter;
-use tracing::{instrument, Level};
+use tracing::{instrument, warn, Level, Span};
+use tracing_subscriber::fmt::writer::BoxMakeWriter;
+
+/// Header holding the ID of the run in every request to the API server.
+const RUN_ID_HEADER: &str = "x-deka-run-id";
 
 #[derive(Parser, Debug)]
 #[command(about = "Apply Kubernetes manifests the dumb way.", long_about = None)]
@@ -47,9 +86,47 @@ pub struct GlobalFlags {
     #[arg(long, short = 'D', global = true)]
     debug: bool,
 
-    /// Limit the number of parallel requests. 0 to disable
+    /// Limit the number of parallel requests, lowered automatically while the API server is overloaded. 0 to disable
     #[arg(long, short, global = true, default_value = "10")]
     parallelism: usize,
+
+    /// Let the limit of parallel requests grow up to this while the API server copes with them. Defaults to twice --parallelism
+    #[arg(long, global = true)]
+    max_parallelism: Option<usize>,
+
+    /// Limit the average number of requests per second. 0 to disable
+    #[arg(long, global = true, default_value = "0")]
+    qps: f64,
+
+    /// Number of requests allowed at once before --qps applies
+    #[arg(long, global = true, default_value = "10")]
+    burst: u32,
+
+    /// Log progress instead of displaying it live, even on an interactive terminal
+    #[arg(long, global = true)]
+    no_progress: bool,
+
+    /// Export traces to this OpenTelemetry collector, e.g. http://localhost:4317
+    #[arg(long, global = true)]
+    otlp_endpoint: Option<String>,
+
+    /// Protocol used to export traces with --otlp-endpoint
+    #[arg(long, global = true, value_enum, default_value_t = OtlpProtocol::Grpc)]
+    otlp_protocol: OtlpProtocol,
+
+    /// Leave the data of Secrets and fields configured as sensitive unmasked in logs, traces, printed objects and reports
+    #[arg(long, global = true)]
+    show_secrets: bool,
+}
+
+impl GlobalFlags {
+    /// Whether progress is displayed live rather than logged, which is only
+    /// readable on a terminal, and would get mixed with structured logs.
+    fn live_progress(&self) -> bool {
+        !self.no_progress
+            && matches!(self.output, OutputFormat::Plain | OutputFormat::Pretty)
+            && io::stderr().is_terminal()
+    }
 }
 
 #[derive(Clone, Debug, clap::ValueEnum)]
@@ -60,6 +137,19 @@ pub enum OutputFormat {
     Pretty,
 }
 
+#[derive(Clone, Copy, Debug, clap::ValueEnum)]
+pub enum PrintFormat {
+    Json,
+    Yaml,
+}
+
+#[derive(Clone, Debug, PartialEq, clap::ValueEnum)]
+pub enum PreflightMode {
+    Fail,
+    Warn,
+    Off,
+}
+
 #[derive(Args, Debug)]
 pub struct ApplyFlags {
     /// The file that contains the configuration to apply
@@ -73,6 +163,99 @@ pub struct ApplyFlags {
     /// The length of time to wait before giving up in seconds. 0 to wait indefinitely
     #[arg(long, default_value = "300")]
     timeout: u64,
+
+    /// The length of time to wait before giving up on each object in seconds. 0 to wait indefinitely
+    #[arg(long, default_value = "0")]
+    object_timeout: u64,
+
+    /// Seconds without any object succeeding, while all remaining ones failed, before giving up. 0 to disable
+    #[arg(long, default_value = "0")]
+    stall_timeout: u64,
+
+    /// Seconds to wait for in-flight requests to complete when cancelled by a signal
+    #[arg(long, default_value = "10")]
+    grace_period: u64,
+
+    /// Consecutive connection errors or 5xx responses before pausing all attempts until the API server is reachable. 0 to disable
+    #[arg(long, default_value = "5")]
+    breaker_threshold: usize,
+
+    /// Merge objects defined more than once with the same content instead of failing
+    #[arg(long)]
+    merge_duplicates: bool,
+
+    /// What to do when objects seem to never be applied, e.g. of an unknown kind. Only warns by default, as an operator of the input may register kinds or create namespaces once running
+    #[arg(long, value_enum, default_value_t = PreflightMode::Warn)]
+    preflight: PreflightMode,
+
+    /// Create the namespaces objects are applied to if they don't exist
+    #[arg(long)]
+    create_namespace: bool,
+
+    /// Retry objects missing a CRD or Namespace as soon as it becomes established or active
+    #[arg(long)]
+    event_driven: bool,
+
+    /// Print the objects as persisted by the API server once applied to stdout, in input order. Logs are written to stderr instead
+    #[arg(long, value_enum)]
+    print_objects: Option<PrintFormat>,
+
+    /// Write a JUnit XML report with one testcase per object to this path
+    #[arg(long)]
+    report_junit: Option<PathBuf>,
+
+    /// Write a JSON document describing the run and the outcome of each object to this path
+    #[arg(long)]
+    result_file: Option<PathBuf>,
+
+    /// Write metrics in the Prometheus text format to this path once done, e.g. for the textfile collector of the node exporter
+    #[arg(long)]
+    metrics_file: Option<PathBuf>,
+
+    /// Serve metrics in the Prometheus text format on this address while applying, e.g. 127.0.0.1:9090
+    #[arg(long)]
+    metrics_addr: Option<SocketAddr>,
+
+    /// Stamp applied objects with the ID of the run as the deka.ndrpnt.dev/run-id annotation
+    #[arg(long)]
+    annotate_run_id: bool,
+
+    /// Path to a YAML configuration file, e.g. for the retry policy
+    #[arg(long)]
+    config: Option<PathBuf>,
+
+    /// Retry strategy among exponential, constant, decorrelated-jitter and fibonacci. Defaults to exponential
+    #[arg(long)]
+    retry_strategy: Option<Strategy>,
+
+    /// Interval after the first failed attempt, e.g. 400ms. Defaults to 400ms
+    #[arg(long, value_parser = deka::parse_duration)]
+    retry_initial: Option<Duration>,
+
+    /// Maximum interval between attempts, e.g. 1m. Defaults to 30s
+    #[arg(long, value_parser = deka::parse_duration)]
+    retry_max_interval: Option<Duration>,
+
+    /// Growth of the interval between attempts with the exponential strategy. Defaults to 5
+    #[arg(long)]
+    retry_multiplier: Option<f64>,
+
+    /// Randomization of the interval between attempts, from 0 to 1. Defaults to 0.5
+    #[arg(long, value_parser = deka::backoff::parse_jitter)]
+    retry_jitter: Option<f64>,
+
+    /// Give up on an object after this many attempts. Retries indefinitely by default
+    #[arg(long)]
+    retry_max_attempts: Option<usize>,
+}
+
+/// Contents of the file given with `--config`.
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, rename_all = "camelCase", deny_unknown_fields)]
+struct FileConfig {
+    retry: RetryPolicy,
+    overrides: Vec<KindOverride>,
+    redact: Vec<SensitiveFields>,
 }
 
 #[derive(Subcommand, Debug)]
@@ -85,59 +268,439 @@ enum Commands {
 }
 
 #[tokio::main]
-async fn main() -> Result<()> {
+async fn main() -> Result<ExitCode> {
     let cli = Cli::parse();
 
+    // Only warnings are worth interrupting the live view, errors end up in
+    // the summary anyway.
+    let screen = cli.flags.live_progress().then(live::Screen::default);
+    let lvl = match screen {
+        Some(_) => cli
+            .flags
+            .verbose
+            .tracing_level_filter()
+            .min(LevelFilter::WARN),
+        None => cli.flags.verbose.tracing_level_filter(),
+    };
+    // Keep stdout for the objects printed.
+    let redactor = Redactor::new(cli.flags.show_secrets);
+    let writer = match &cli.command {
+        Commands::Apply { flags } if flags.print_objects.is_some() => {
+            BoxMakeWriter::new(io::stderr)
+        }
+        _ => BoxMakeWriter::new(io::stdout),
+    };
+    let writer = match &screen {
+        Some(s) => BoxMakeWriter::new(s.make_writer(writer)),
+        None => writer,
+    };
+    let writer = BoxMakeWriter::new(RedactingMakeWriter::new(writer, redactor.clone()));
+    let tracer_provider = match &cli.flags.otlp_endpoint {
+        Some(e) => Some(telemetry::tracer_provider(
+            e,
+            cli.flags.otlp_protocol,
+            redactor.clone(),
+        )?),
+        None => None,
+    };
     init_telemetry(
-        cli.flags.verbose.tracing_level_filter(),
+        lvl,
         cli.flags.output.clone(),
         cli.flags.debug,
+        writer,
+        tracer_provider
+            .as_ref()
+            .map(|p| (p, cli.flags.verbose.tracing_level_filter())),
     )?;
 
-    match cli.command {
-        Commands::Apply { flags } => apply(&cli.flags, &flags).await,
+    let result = match cli.command {
+        Commands::Apply { flags } => apply(&cli.flags, &flags, &redactor, screen).await,
+    };
+    if let Some(p) = tracer_provider {
+        if let Err(e) = p.shutdown() {
+            eprintln!("Failed to export traces: {e}");
+        }
+    }
+    match result {
+        Ok(code) => Ok(code),
+        Err(f) => {
+            let report = format!("{:?}", f.report);
+            eprintln!("Error: {}", redactor.redact_text(&report));
+            Ok(ExitCode::from(f.code))
+        }
     }
 }
 
-#[instrument(skip_all, err)]
-async fn apply(gflags: &GlobalFlags, flags: &ApplyFlags) -> Result<()> {
-    let objects = read_objects(&flags.filename)?;
-    let config = build_config(&gflags.kubeconfig).await?;
-    let client = &build_client(config, gflags.parallelism)?;
-    let backoff = &ExponentialBackoffBuilder::new()
-        .with_initial_interval(Duration::from_millis(400))
-        .with_randomization_factor(0.5)
-        .with_multiplier(5.0)
-        .with_max_interval(Duration::from_secs(30))
-        .with_max_elapsed_time(match flags.timeout {
-            0 => None,
-            t => Some(Duration::from_secs(t)),
-        })
-        .build();
+#[instrument(skip_all, fields(run_id), err)]
+async fn apply(
+    gflags: &GlobalFlags,
+    flags: &ApplyFlags,
+    redactor: &Redactor,
+    screen: Option<live::Screen>,
+) -> Result<ExitCode, Failure> {
+    let run_id = new_run_id();
+    Span::current().record("run_id", run_id.as_str());
+    let cancel = CancellationToken::new();
+    tokio::spawn(cancel_on_signal(cancel.clone()));
+
+    // Sensitive fields must be known before reading the input, for errors
+    // reading it to be redacted.
+    let config = read_config(&flags.config).exit_code(exit::INVALID_INPUT)?;
+    redactor.add_fields(config.redact);
+    let objects = read_objects(&flags.filename, redactor).exit_code(exit::INVALID_INPUT)?;
+    let kubeconfig = build_config(&gflags.kubeconfig)
+        .await
+        .exit_code(exit::UNREACHABLE)?;
+    let mut registry = Registry::default();
+    let metrics = (flags.metrics_file.is_some() || flags.metrics_addr.is_some())
+        .then(|| Metrics::new(&mut registry));
+    let registry = Arc::new(registry);
+    if let Some(addr) = flags.metrics_addr {
+        let server = metrics::serve(Arc::clone(&registry), addr);
+        tokio::spawn(async move {
+            if let Err(e) = server.await {
+                warn!(error = %e, "Failed to serve metrics");
+            }
+        });
+    }
+    let throttling = RetryAfterLayer::new();
+    let client = &build_client(kubeconfig, gflags, metrics.as_ref(), &throttling, &run_id)
+        .exit_code(exit::UNREACHABLE)?;
+    // Analyze the input as read, so that problems point at its documents.
+    let analysis = analyze(&objects, client, gflags, flags).await;
+    let (positions, objects): (Vec<_>, Vec<_>) = analysis::check_duplicates(
+        objects,
+        gflags.namespace.as_deref(),
+        client,
+        &analysis.kinds.scopes,
+        flags.merge_duplicates,
+    )
+    .map_err(|e| input_diagnostic(&flags.filename, &e, e.errors()))
+    .exit_code(exit::INVALID_INPUT)?
+    .into_iter()
+    .unzip();
+    let scopes = analysis.kinds.scopes.clone();
+    let objects = preflight(objects, analysis, flags)?;
+    let retry = retry_policy(config.retry, flags);
+    let backoff = &retry.build();
+
+    let (events, live) = match screen {
+        Some(s) => {
+            let (events, live) = live::spawn(objects.len(), redactor.clone(), s);
+            (Some(events), Some(live))
+        }
+        None => (None, None),
+    };
+    let server_version = match &flags.result_file {
+        Some(_) => server_version(client).await,
+        None => None,
+    };
 
-    deka::apply_objects(
+    let started = Instant::now();
+    let result = deka::apply_objects(
         objects,
         client,
         &flags.field_manager,
         gflags.namespace.as_deref(),
         backoff,
+        &ApplyParams {
+            event_driven: flags.event_driven,
+            stall_timeout: seconds(flags.stall_timeout),
+            deadline: seconds(flags.timeout),
+            object_timeout: seconds(flags.object_timeout),
+            cancel: cancel.clone(),
+            grace_period: Some(Duration::from_secs(flags.grace_period)),
+            breaker_threshold: (flags.breaker_threshold > 0).then_some(flags.breaker_threshold),
+            overrides: config.overrides,
+            retry,
+            events,
+            keep_applied: flags.print_objects.is_some(),
+            metrics,
+            run_id: flags.annotate_run_id.then(|| run_id.clone()),
+            scopes,
+            throttling: Some(throttling),
+        },
+    )
+    .await;
+    if let Some(live) = live {
+        let _ = live.await;
+    }
+
+    let report = match &result {
+        Ok(r) => r,
+        Err(e) => e.report(),
+    };
+    if let Some(format) = flags.print_objects {
+        print_objects(report, format, redactor)?;
+    }
+    let elapsed = started.elapsed();
+    eprint!(
+        "{}",
+        redactor.redact_text(&summary::format(report, elapsed))
+    );
+    if let Some(path) = &flags.report_junit {
+        let junit = junit::format(report, elapsed, redactor);
+        std::fs::write(path, junit.as_bytes()).into_diagnostic()?;
+    }
+    if let Some(path) = &flags.metrics_file {
+        metrics::write(&registry, path)?;
+    }
+    if let Some(path) = &flags.result_file {
+        let cluster = result_file::Cluster { server_version };
+        let inputs = result_file::Inputs {
+            filename: &flags.filename,
+            namespace: gflags.namespace.as_deref(),
+        };
+        let result = result_file::ResultFile::new(
+            report,
+            &positions,
+            elapsed,
+            &run_id,
+            inputs,
+            cluster,
+            &flags.field_manager,
+        );
+        let mut result = serde_json::to_value(result).into_diagnostic()?;
+        redactor.redact_strings(&mut result);
+        let file = File::create(path).into_diagnostic()?;
+        serde_json::to_writer_pretty(file, &result).into_diagnostic()?;
+    }
+
+    match result {
+        Ok(_) => Ok(ExitCode::SUCCESS),
+        Err(_) if cancel.is_cancelled() => Ok(ExitCode::from(exit::CANCELLED)),
+        Err(e) => {
+            let code = exit::of_failed(e.report());
+            Err(e).into_diagnostic().exit_code(code)
+        }
+    }
+}
+
+/// Prints the applied objects as a stream of YAML documents, or of JSON
+/// documents one per line, with sensitive values masked.
+fn print_objects(
+    report: &deka::report::Report,
+    format: PrintFormat,
+    redactor: &Redactor,
+) -> Result<()> {
+    let mut stdout = io::stdout().lock();
+    for object in report.objects.iter().filter_map(|o| o.applied.as_ref()) {
+        let mut object = serde_json::to_value(object).into_diagnostic()?;
+        redactor.redact_object(&mut object);
+        match format {
+            PrintFormat::Json => {
+                serde_json::to_writer(&mut stdout, &object).into_diagnostic()?;
+                writeln!(stdout).into_diagnostic()?;
+            }
+            PrintFormat::Yaml => {
+                writeln!(stdout, "---").into_diagnostic()?;
+                serde_yaml::to_writer(&mut stdout, &object).into_diagnostic()?;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Generates a random ID for a run, formatted as a UUID like the audit IDs of
+/// the API server.
+fn new_run_id() -> String {
+    let mut bytes: [u8; 16] = rand::random();
+    bytes[6] = (bytes[6] & 0x0f) | 0x40;
+    bytes[8] = (bytes[8] & 0x3f) | 0x80;
+    let hex = |range: std::ops::Range<usize>| -> String {
+        bytes[range].iter().map(|b| format!("{b:02x}")).collect()
+    };
+    format!(
+        "{}-{}-{}-{}-{}",
+        hex(0..4),
+        hex(4..6),
+        hex(6..8),
+        hex(8..10),
+        hex(10..16)
     )
-    .await
-    .into_diagnostic()
 }
 
+/// Returns the version of the API server, if it can be retrieved.
+async fn server_version(client: &Client) -> Option<String> {
+    match client.apiserver_version().await {
+        Ok(info) => Some(info.git_version),
+        Err(e) => {
+            warn!(error = %e, "Failed to retrieve the version of the API server");
+            None
+        }
+    }
+}
+
+/// Cancels `cancel` upon SIGINT or SIGTERM, and exits right away upon a second
+/// signal.
+async fn cancel_on_signal(cancel: CancellationToken) {
+    shutdown_signal().await;
+    warn!("Cancelling, waiting for in-flight requests (signal again to exit now)");
+    cancel.cancel();
+
+    shutdown_signal().await;
+    std::process::exit(exit::CANCELLED.into());
+}
+
+/// Resolves upon SIGINT, or SIGTERM on Unix.
+async fn shutdown_signal() {
+    #[cfg(unix)]
+    let terminate = async {
+        tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate())
+            .expect("SIGTERM handler should be installable")
+            .recv()
+            .await
+    };
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<Option<()>>();
+
+    tokio::select! {
+        _ = tokio::signal::ctrl_c() => {}
+        _ = terminate => {}
+    }
+}
+
+/// Converts a number of seconds given as flag, where 0 means no limit.
+fn seconds(s: u64) -> Option<Duration> {
+    match s {
+        0 => None,
+        s => Some(Duration::from_secs(s)),
+    }
+}
+
+/// What is known of the input before applying it.
+#[derive(Default)]
+struct Analysis {
+    kinds: ResolvedKinds,
+    namespaces: Vec<UnreachableNamespace>,
+}
+
+/// Resolves the kinds of `objects` and finds the namespaces they can't reach,
+/// unless preflight checks are off and namespaces are not to be created.
+#[instrument(level = Level::DEBUG, skip_all)]
+async fn analyze(
+    objects: &[DynamicObject],
+    client: &Client,
+    gflags: &GlobalFlags,
+    flags: &ApplyFlags,
+) -> Analysis {
+    if flags.preflight == PreflightMode::Off && !flags.create_namespace {
+        return Analysis::default();
+    }
+
+    let kinds = analysis::resolve_kinds(objects, client).await;
+    let namespaces =
+        analysis::find_unreachable_namespaces(objects, gflags.namespace.as_deref(), client, &kinds)
+            .await;
+    Analysis { kinds, namespaces }
+}
+
+/// Checks that every object can eventually be applied according to
+/// `analysis`, so that the command fails fast instead of retrying until
+/// timeout. Missing namespaces are added to the objects to apply if requested.
+fn preflight(
+    mut objects: Vec<DynamicObject>,
+    analysis: Analysis,
+    flags: &ApplyFlags,
+) -> Result<Vec<DynamicObject>, Failure> {
+    let Analysis { kinds, namespaces } = analysis;
+    let mut problems: Vec<&dyn Display> = kinds
+        .unresolvable
+        .iter()
+        .map(|k| k as &dyn Display)
+        .collect();
+    if flags.create_namespace {
+        for ns in &namespaces {
+            warn!(namespace = ns.namespace, "Adding missing namespace");
+        }
+        objects.extend(namespaces.iter().map(UnreachableNamespace::to_object));
+    } else {
+        problems.extend(namespaces.iter().map(|n| n as &dyn Display));
+    }
+
+    match flags.preflight {
+        PreflightMode::Fail if !problems.is_empty() => Err(Failure {
+            code: exit::INVALID_INPUT,
+            report: input_diagnostic(
+                &flags.filename,
+                "Object(s) that can never be applied in input",
+                problems,
+            ),
+        }),
+        PreflightMode::Warn => {
+            for p in problems {
+                warn!("{}", p);
+            }
+            Ok(objects)
+        }
+        _ => Ok(objects),
+    }
+}
+
+/// Lists problems found in the input file, each of which mentions the
+/// position of the documents concerned.
+fn input_diagnostic(
+    path: &Path,
+    title: impl Display,
+    problems: impl IntoIterator<Item = impl Display>,
+) -> miette::Report {
+    let details: Vec<_> = problems
+        .into_iter()
+        .map(|p| format!("  {}: {}", path.display(), p))
+        .collect();
+    miette!("{}:\n{}", title, details.join("\n"))
+}
+
+/// Reads the objects of the input, learning the sensitive values of each
+/// document as soon as it is parsed, so that they are masked from the errors
+/// of the following ones too.
 #[instrument(level = Level::DEBUG, skip_all, err)]
-fn read_objects(path: &PathBuf) -> Result<Vec<DynamicObject>> {
+fn read_objects(path: &PathBuf, redactor: &Redactor) -> Result<Vec<DynamicObject>> {
     match path.to_string_lossy().as_ref() {
         "-" => Deserializer::from_reader(io::stdin().lock()),
         _ => Deserializer::from_reader(File::open(path).into_diagnostic()?),
     }
     .map(serde_yaml::Value::deserialize)
-    .map(|v| v.and_then(serde_yaml::from_value))
+    .map(|v| {
+        let v = v?;
+        redactor.learn_values(serde_json::to_value(&v).ok());
+        serde_yaml::from_value(v)
+    })
     .map(IntoDiagnostic::into_diagnostic)
     .collect()
 }
 
+#[instrument(level = Level::DEBUG, skip_all, err)]
+fn read_config(path: &Option<PathBuf>) -> Result<FileConfig> {
+    match path {
+        Some(p) => serde_yaml::from_reader(File::open(p).into_diagnostic()?).into_diagnostic(),
+        None => Ok(FileConfig::default()),
+    }
+}
+
+/// Overrides the retry policy of the configuration file with flags.
+fn retry_policy(mut policy: RetryPolicy, flags: &ApplyFlags) -> RetryPolicy {
+    if let Some(s) = flags.retry_strategy {
+        policy.strategy = s;
+    }
+    if let Some(i) = flags.retry_initial {
+        policy.initial = i;
+    }
+    if let Some(i) = flags.retry_max_interval {
+        policy.max_interval = i;
+    }
+    if let Some(m) = flags.retry_multiplier {
+        policy.multiplier = m;
+    }
+    if let Some(j) = flags.retry_jitter {
+        policy.jitter = j;
+    }
+    if flags.retry_max_attempts.is_some() {
+        policy.max_attempts = flags.retry_max_attempts;
+    }
+    policy
+}
+
 #[instrument(level = Level::DEBUG, skip_all, err)]
 async fn build_config(path: &Option<PathBuf>) -> Result<Config> {
     match path {
@@ -155,40 +718,101 @@ async fn build_config(path: &Option<PathBuf>) -> Result<Config> {
 }
 
 #[instrument(level = Level::DEBUG, skip_all, err)]
-fn build_client(config: Config, parallelism: usize) -> Result<Client> {
-    let builder = ClientBuilder::try_from(config).into_diagnostic()?;
-    let client = match parallelism {
+fn build_client(
+    mut config: Config,
+    gflags: &GlobalFlags,
+    metrics: Option<&Metrics>,
+    throttling: &RetryAfterLayer,
+    run_id: &str,
+) -> Result<Client> {
+    // Tells which run made a request in the audit logs of the API server,
+    // which record the user agent, and to proxies in front of it. Audit IDs
+    // are left to the API server, as they must be unique per request.
+    let user_agent = format!(
+        "{}/{} (run {})",
+        env!("CARGO_PKG_NAME"),
+        env!("CARGO_PKG_VERSION"),
+        run_id
+    );
+    config.headers.extend([
+        (
+            USER_AGENT,
+            HeaderValue::from_str(&user_agent).into_diagnostic()?,
+        ),
+        (
+            HeaderName::from_static(RUN_ID_HEADER),
+            HeaderValue::from_str(run_id).into_diagnostic()?,
+        ),
+    ]);
+
+    let builder = ClientBuilder::try_from(config)
+        .into_diagnostic()?
+        .with_layer(&tower::util::option_layer(metrics.map(Metrics::layer)))
+        .with_layer(throttling);
+    let builder = builder.with_layer(&tower::util::option_layer(
+        (gflags.qps > 0.0).then(|| RateLimitLayer::new(gflags.qps, gflags.burst)),
+    ));
+    let client = match gflags.parallelism {
         0 => builder.build(),
         p => builder
-            .with_layer(&tower::limit::concurrency::ConcurrencyLimitLayer::new(p))
+            .with_layer(&AdaptiveConcurrencyLayer::new(
+                p,
+                gflags.max_parallelism.unwrap_or(2 * p),
+            ))
             .build(),
     };
 
     Ok(client)
 }
 
-pub fn init_telemetry(lvl: LevelFilter, output: OutputFormat, debug: bool) -> Result<()> {
+pub fn init_telemetry(
+    lvl: LevelFilter,
+    output: OutputFormat,
+    debug: bool,
+    writer: BoxMakeWriter,
+    otlp: Option<(&SdkTracerProvider, LevelFilter)>,
+) -> Result<()> {
     use tracing_subscriber::{
         filter::Targets, layer::SubscriberExt, util::SubscriberInitExt, Layer, Registry,
     };
 
-    let stdout = match output {
-        OutputFormat::Json => tracing_subscriber::fmt::layer().json().boxed(),
-        OutputFormat::Logfmt => tracing_logfmt::layer().boxed(),
-        OutputFormat::Plain => tracing_subscriber::fmt::layer().boxed(),
-        OutputFormat::Pretty => tracing_subscriber::fmt::layer().pretty().boxed(),
+    let logs = match output {
+        OutputFormat::Json => tracing_subscriber::fmt::layer()
+            .json()
+            .with_writer(writer)
+            .boxed(),
+        OutputFormat::Logfmt => tracing_logfmt::builder()
+            .layer()
+            .with_writer(writer)
+            .boxed(),
+        OutputFormat::Plain => tracing_subscriber::fmt::layer().with_writer(writer).boxed(),
+        OutputFormat::Pretty => tracing_subscriber::fmt::layer()
+            .pretty()
+            .with_writer(writer)
+            .boxed(),
     }
     .with_filter(lvl);
 
-    let stdout = if debug {
-        stdout.boxed()
+    // Spans are exported whether logs are displayed or not.
+    let traces = otlp.map(|(provider, lvl)| {
+        tracing_opentelemetry::layer()
+            .with_tracer(provider.tracer(env!("CARGO_CRATE_NAME")))
+            .with_filter(lvl)
+    });
+
+    let only_crate = Targets::default().with_target(env!("CARGO_CRATE_NAME"), Level::TRACE);
+    let (logs, traces) = if debug {
+        (logs.boxed(), traces.boxed())
     } else {
-        let only_crate = Targets::default().with_target(env!("CARGO_CRATE_NAME"), Level::TRACE);
-        stdout.with_filter(only_crate).boxed()
+        (
+            logs.with_filter(only_crate.clone()).boxed(),
+            traces.with_filter(only_crate).boxed(),
+        )
     };
 
     Registry::default()
-        .with(stdout)
+        .with(logs)
+        .with(traces)
         .try_init()
         .into_diagnostic()
 }
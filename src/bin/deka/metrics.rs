@@ -0,0 +1,66 @@
+//! Exposition of metrics in the Prometheus text format.
+
+use miette::{IntoDiagnostic, Result};
+use prometheus_client::{encoding::text::encode, registry::Registry};
+use std::{fs, net::SocketAddr, path::Path, sync::Arc, time::Duration};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::{TcpListener, TcpStream},
+};
+use tracing::{debug, info, warn};
+
+const CONTENT_TYPE: &str = "application/openmetrics-text; version=1.0.0; charset=utf-8";
+
+/// How long to wait after failing to accept a connection, e.g. for lack of
+/// file descriptors, before accepting again.
+const ACCEPT_BACKOFF: Duration = Duration::from_secs(1);
+
+/// Writes metrics to `path` atomically, so that the textfile collector of the
+/// node exporter never reads a partial file.
+pub fn write(registry: &Registry, path: &Path) -> Result<()> {
+    let mut text = String::new();
+    encode(&mut text, registry).into_diagnostic()?;
+    let tmp = path.with_extension("tmp");
+    fs::write(&tmp, text).into_diagnostic()?;
+    fs::rename(&tmp, path).into_diagnostic()
+}
+
+/// Serves metrics over HTTP on `addr`, whatever the path requested. Never
+/// returns unless binding fails.
+pub async fn serve(registry: Arc<Registry>, addr: SocketAddr) -> Result<()> {
+    let listener = TcpListener::bind(addr).await.into_diagnostic()?;
+    info!(%addr, "Serving metrics");
+    loop {
+        let (stream, peer) = match listener.accept().await {
+            Ok(accepted) => accepted,
+            Err(e) => {
+                warn!(error = %e, "Failed to accept metrics connection");
+                tokio::time::sleep(ACCEPT_BACKOFF).await;
+                continue;
+            }
+        };
+        let registry = Arc::clone(&registry);
+        tokio::spawn(async move {
+            if let Err(e) = respond(stream, &registry).await {
+                debug!(%peer, error = %e, "Failed to serve metrics");
+            }
+        });
+    }
+}
+
+async fn respond(mut stream: TcpStream, registry: &Registry) -> std::io::Result<()> {
+    // The request itself does not matter, only wait for it to be sent.
+    let mut request = [0; 1024];
+    let _ = stream.read(&mut request).await?;
+
+    let mut body = String::new();
+    encode(&mut body, registry).map_err(std::io::Error::other)?;
+    let head = format!(
+        "HTTP/1.1 200 OK\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+        CONTENT_TYPE,
+        body.len()
+    );
+    stream.write_all(head.as_bytes()).await?;
+    stream.write_all(body.as_bytes()).await?;
+    stream.shutdown().await
+}
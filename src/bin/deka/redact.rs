@@ -0,0 +1,127 @@
+//! Redaction of logs, which are written before sensitive values are known.
+
+use deka::redact::Redactor;
+use std::io::{self, Write};
+use tracing_subscriber::fmt::{writer::BoxMakeWriter, MakeWriter};
+
+/// Makes writers masking the values learned by a [`Redactor`].
+pub struct RedactingMakeWriter {
+    inner: BoxMakeWriter,
+    redactor: Redactor,
+}
+
+impl RedactingMakeWriter {
+    pub fn new(inner: BoxMakeWriter, redactor: Redactor) -> Self {
+        Self { inner, redactor }
+    }
+}
+
+impl<'a> MakeWriter<'a> for RedactingMakeWriter {
+    type Writer = RedactingWriter<'a, Box<dyn Write + 'a>>;
+
+    fn make_writer(&'a self) -> Self::Writer {
+        RedactingWriter {
+            inner: self.inner.make_writer(),
+            redactor: &self.redactor,
+        }
+    }
+}
+
+/// Masks sensitive values in each buffer written, which holds a whole event
+/// as formatted by `tracing_subscriber`.
+pub struct RedactingWriter<'a, W> {
+    inner: W,
+    redactor: &'a Redactor,
+}
+
+impl<W: Write> Write for RedactingWriter<'_, W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match std::str::from_utf8(buf) {
+            Ok(text) => self
+                .inner
+                .write_all(self.redactor.redact_text(text).as_bytes())?,
+            Err(_) => self.inner.write_all(buf)?,
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::{Arc, Mutex};
+    use tracing_subscriber::layer::SubscriberExt;
+
+    #[derive(Clone, Default)]
+    struct Buffer(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for Buffer {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn redact_writes() {
+        let redactor = Redactor::new(false);
+        let secret = serde_json::from_value(serde_json::json!({
+            "apiVersion": "v1",
+            "kind": "Secret",
+            "metadata": { "name": "example" },
+            "stringData": { "token": "s3cr3t-t0ken" },
+        }))
+        .unwrap();
+        redactor.learn([&secret]);
+
+        let mut out = vec![];
+        let mut writer = RedactingWriter {
+            inner: &mut out,
+            redactor: &redactor,
+        };
+        write!(writer, "ERROR error=\"invalid token s3cr3t-t0ken\"").unwrap();
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "ERROR error=\"invalid token ***\""
+        );
+    }
+
+    #[test]
+    fn redact_json_logs() {
+        let redactor = Redactor::new(false);
+        redactor.learn_values([serde_json::json!({
+            "apiVersion": "v1",
+            "kind": "Secret",
+            "stringData": { "tls.key": "-----BEGIN KEY-----\nMIIE\"pa\\ss\n-----END KEY-----" },
+        })]);
+        let key = "-----BEGIN KEY-----\nMIIE\"pa\\ss\n-----END KEY-----";
+
+        let buffer = Buffer::default();
+        let writer = {
+            let buffer = buffer.clone();
+            BoxMakeWriter::new(move || buffer.clone())
+        };
+        let subscriber = tracing_subscriber::registry().with(
+            tracing_subscriber::fmt::layer()
+                .json()
+                .with_writer(RedactingMakeWriter::new(writer, redactor)),
+        );
+        tracing::subscriber::with_default(subscriber, || {
+            tracing::error!(error = %format!("invalid key {key}"), "Display");
+            tracing::error!(error = ?key, "Debug");
+        });
+
+        let out = String::from_utf8(buffer.0.lock().unwrap().clone()).unwrap();
+        assert!(!out.contains("pa\\"), "{out}");
+        assert!(!out.contains("MIIE"), "{out}");
+        assert!(out.contains(r#""error":"invalid key ***""#), "{out}");
+        assert!(out.contains(r#""error":"\"***\"""#), "{out}");
+    }
+}
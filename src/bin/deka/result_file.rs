@@ -0,0 +1,232 @@
+//! JSON document describing a run, for tools that would otherwise have to
+//! parse logs.
+//!
+//! The document is versioned with its `apiVersion`: fields may be added to a
+//! version, but never removed or changed.
+
+use crate::summary::outcome;
+use deka::report::{ObjectReport, Report};
+use serde::Serialize;
+use std::{path::Path, time::Duration};
+
+pub const API_VERSION: &str = "deka.ndrpnt.dev/v1";
+pub const KIND: &str = "ApplyResult";
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ResultFile<'a> {
+    pub api_version: &'static str,
+    pub kind: &'static str,
+    /// Identifies the run, as sent to the API server in the `X-Deka-Run-Id`
+    /// header and the user agent of requests.
+    pub run_id: &'a str,
+    pub inputs: Inputs<'a>,
+    pub cluster: Cluster,
+    pub field_manager: &'a str,
+    /// Whether every object was applied or deleted.
+    pub success: bool,
+    pub duration_seconds: f64,
+    pub objects: Vec<Object<'a>>,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Inputs<'a> {
+    pub filename: &'a Path,
+    /// Namespace of the objects that don't set one, if given.
+    pub namespace: Option<&'a str>,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Cluster {
+    /// Version of the API server, e.g. `v1.31.0`, unless it could not be
+    /// retrieved.
+    pub server_version: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Object<'a> {
+    /// Position of the object in the input, starting at 0. Unset for objects
+    /// not read from the input, i.e. namespaces added by `--create-namespace`.
+    pub index: Option<usize>,
+    /// Identifies the object for humans, as in logs.
+    pub label: &'a str,
+    /// Unset along with `kind`, `namespace` and `name` when the `apiVersion`
+    /// of the object cannot be parsed.
+    pub group: Option<&'a str>,
+    pub kind: Option<&'a str>,
+    /// Also unset for objects of a cluster-scoped kind.
+    pub namespace: Option<&'a str>,
+    pub name: Option<&'a str>,
+    /// `apply` or `delete`, unless the action annotation is invalid.
+    pub action: Option<&'a str>,
+    /// What happened to the object, e.g. `serverside-applied` or `timed-out`.
+    pub outcome: &'static str,
+    pub attempts: usize,
+    pub throttled_attempts: usize,
+    pub duration_seconds: f64,
+    /// The error the object was given up on, if it was.
+    pub error: Option<String>,
+    pub failed_attempts: Vec<FailedAttempt<'a>>,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct FailedAttempt<'a> {
+    pub attempt: usize,
+    /// Time from the start of the run until the attempt failed.
+    pub elapsed_seconds: f64,
+    /// Why the attempt failed, e.g. `missing-kind` or `throttled`.
+    pub cause: &'a str,
+    pub error: &'a str,
+}
+
+impl<'a> ResultFile<'a> {
+    /// Describes a run, given the positions in the input of the objects of
+    /// `report`, which lack those of the objects added after the input.
+    pub fn new(
+        report: &'a Report,
+        positions: &[usize],
+        elapsed: Duration,
+        run_id: &'a str,
+        inputs: Inputs<'a>,
+        cluster: Cluster,
+        field_manager: &'a str,
+    ) -> Self {
+        Self {
+            api_version: API_VERSION,
+            kind: KIND,
+            run_id,
+            inputs,
+            cluster,
+            field_manager,
+            success: report.is_success(),
+            duration_seconds: elapsed.as_secs_f64(),
+            objects: report
+                .objects
+                .iter()
+                .enumerate()
+                .map(|(i, o)| object(positions.get(i).copied(), o))
+                .collect(),
+        }
+    }
+}
+
+fn object(index: Option<usize>, o: &ObjectReport) -> Object<'_> {
+    let identity = o.identity.as_ref();
+    Object {
+        index,
+        label: &o.label,
+        group: identity.map(|i| i.group.as_str()),
+        kind: identity.map(|i| i.kind.as_str()),
+        namespace: identity.and_then(|i| i.namespace.as_deref()),
+        name: identity.map(|i| i.name.as_str()),
+        action: o.action.as_ref().map(AsRef::as_ref),
+        outcome: outcome(o),
+        attempts: o.attempts,
+        throttled_attempts: o.throttled,
+        duration_seconds: o.duration.as_secs_f64(),
+        error: o.error.as_ref().map(ToString::to_string),
+        failed_attempts: o
+            .failures
+            .iter()
+            .map(|f| FailedAttempt {
+                attempt: f.attempt,
+                elapsed_seconds: f.elapsed.as_secs_f64(),
+                cause: f.cause.as_ref(),
+                error: &f.error,
+            })
+            .collect(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use deka::{Action, ApplyError};
+    use serde_json::json;
+
+    #[test]
+    fn leave_added_objects_without_index() {
+        let report = Report {
+            objects: vec![ObjectReport::default(), ObjectReport::default()],
+        };
+        let result = ResultFile::new(
+            &report,
+            &[3],
+            Duration::ZERO,
+            "run-1",
+            Inputs {
+                filename: Path::new("-"),
+                namespace: None,
+            },
+            Cluster {
+                server_version: None,
+            },
+            "deka",
+        );
+        let indexes: Vec<_> = result.objects.iter().map(|o| o.index).collect();
+        assert_eq!(indexes, [Some(3), None]);
+    }
+
+    #[test]
+    fn describe_objects() {
+        let report = Report {
+            objects: vec![ObjectReport {
+                label: "example".to_string(),
+                action: Some(Action::Delete),
+                attempts: 1,
+                duration: Duration::from_millis(1500),
+                error: Some(ApplyError::TimedOut {
+                    attempts: 1,
+                    last_error: "boom".to_string(),
+                }),
+                ..Default::default()
+            }],
+        };
+        let result = ResultFile::new(
+            &report,
+            &[3],
+            Duration::from_secs(2),
+            "run-1",
+            Inputs {
+                filename: Path::new("-"),
+                namespace: None,
+            },
+            Cluster {
+                server_version: Some("v1.31.0".to_string()),
+            },
+            "deka",
+        );
+        assert_eq!(
+            serde_json::to_value(result).unwrap(),
+            json!({
+                "apiVersion": "deka.ndrpnt.dev/v1",
+                "kind": "ApplyResult",
+                "runId": "run-1",
+                "inputs": {"filename": "-", "namespace": null},
+                "cluster": {"serverVersion": "v1.31.0"},
+                "fieldManager": "deka",
+                "success": false,
+                "durationSeconds": 2.0,
+                "objects": [{
+                    "index": 3,
+                    "label": "example",
+                    "group": null,
+                    "kind": null,
+                    "namespace": null,
+                    "name": null,
+                    "action": "delete",
+                    "outcome": "timed-out",
+                    "attempts": 1,
+                    "throttledAttempts": 0,
+                    "durationSeconds": 1.5,
+                    "error": "Timed out after 1 attempt(s), last error: boom",
+                    "failedAttempts": [],
+                }],
+            })
+        );
+    }
+}
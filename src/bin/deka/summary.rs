@@ -0,0 +1,183 @@
+//! Summary of an apply printed once it is over.
+
+use deka::{
+    report::{ObjectReport, Report},
+    Action, ApplyError,
+};
+use std::{collections::BTreeMap, fmt::Write, time::Duration};
+
+/// Above this many objects, successful ones are only counted, except for the
+/// slowest ones.
+const COMPACT_THRESHOLD: usize = 20;
+
+/// Number of slowest successful objects listed in the compact form.
+const COMPACT_SLOWEST: usize = 5;
+
+/// Formats what happened to each object, kubectl style, grouped by outcome
+/// with failures last.
+pub fn format(report: &Report, elapsed: Duration) -> String {
+    let mut groups: BTreeMap<(bool, &str), Vec<&ObjectReport>> = BTreeMap::new();
+    for o in &report.objects {
+        groups
+            .entry((o.error.is_some(), outcome(o)))
+            .or_default()
+            .push(o);
+    }
+
+    let counts: Vec<_> = groups
+        .iter()
+        .map(|((_, outcome), objects)| format!("{} {}", objects.len(), outcome))
+        .collect();
+    let mut out = format!(
+        "{} object(s) in {}: {}\n",
+        report.objects.len(),
+        seconds(elapsed),
+        counts.join(", ")
+    );
+
+    let compact = report.objects.len() > COMPACT_THRESHOLD;
+    for ((failed, outcome), mut objects) in groups {
+        let count = objects.len();
+        if compact && !failed && count > COMPACT_SLOWEST {
+            objects.sort_by_key(|o| std::cmp::Reverse(o.duration));
+            objects.truncate(COMPACT_SLOWEST);
+            writeln!(out, "{} ({}, slowest {}):", outcome, count, COMPACT_SLOWEST).unwrap();
+        } else {
+            writeln!(out, "{} ({}):", outcome, count).unwrap();
+        }
+
+        for o in objects {
+            write!(
+                out,
+                "  {} {} ({}, {})",
+                reference(o),
+                outcome,
+                attempts(o.attempts),
+                seconds(o.duration)
+            )
+            .unwrap();
+            match &o.error {
+                Some(e) => writeln!(out, ": {}", e).unwrap(),
+                None => writeln!(out).unwrap(),
+            }
+        }
+    }
+    out
+}
+
+/// Describes what happened to an object, e.g. `serverside-applied`.
+pub fn outcome(o: &ObjectReport) -> &'static str {
+    match (&o.error, o.action) {
+        (None, Some(Action::Delete)) => "deleted",
+        (None, _) => "serverside-applied",
+        (Some(ApplyError::Cancelled { .. }), _) => "cancelled",
+        (Some(ApplyError::DeadlineExceeded { .. }), _) => "deadline-exceeded",
+        (Some(ApplyError::Stalled { .. }), _) => "stalled",
+        (Some(ApplyError::TimedOut { .. }), _) => "timed-out",
+        (Some(_), _) => "failed",
+    }
+}
+
+/// Refers to an object the way `kubectl` does, e.g. `pod/foo -n default`,
+/// falling back to its label if it could not be identified.
+fn reference(o: &ObjectReport) -> String {
+    match &o.identity {
+        Some(i) => match &i.namespace {
+            Some(ns) => format!("{} -n {}", i.resource(), ns),
+            None => i.resource(),
+        },
+        None => o.label.clone(),
+    }
+}
+
+fn attempts(count: usize) -> String {
+    match count {
+        1 => "1 attempt".to_string(),
+        n => format!("{} attempts", n),
+    }
+}
+
+pub fn seconds(d: Duration) -> String {
+    format!("{:.1}s", d.as_secs_f64())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use deka::analysis::ObjectIdentity;
+
+    fn identity(kind: &str, namespace: Option<&str>, name: &str) -> Option<ObjectIdentity> {
+        Some(ObjectIdentity {
+            group: String::new(),
+            kind: kind.to_string(),
+            namespace: namespace.map(str::to_string),
+            name: name.to_string(),
+        })
+    }
+
+    #[test]
+    fn group_by_outcome() {
+        let report = Report {
+            objects: vec![
+                ObjectReport {
+                    label: "pod/a (namespace default)".to_string(),
+                    identity: identity("Pod", Some("default"), "a"),
+                    action: Some(Action::Apply),
+                    attempts: 2,
+                    duration: Duration::from_secs(5),
+                    error: Some(ApplyError::TimedOut {
+                        attempts: 2,
+                        last_error: "boom".to_string(),
+                    }),
+                    ..Default::default()
+                },
+                ObjectReport {
+                    label: "pod/b (namespace default)".to_string(),
+                    identity: identity("Pod", Some("default"), "b"),
+                    action: Some(Action::Apply),
+                    attempts: 2,
+                    duration: Duration::from_secs(1),
+                    ..Default::default()
+                },
+                ObjectReport {
+                    label: "namespace/default".to_string(),
+                    identity: identity("Namespace", None, "default"),
+                    action: Some(Action::Apply),
+                    attempts: 2,
+                    duration: Duration::from_secs(1),
+                    ..Default::default()
+                },
+            ],
+        };
+        assert_eq!(
+            format(&report, Duration::from_millis(5100)),
+            "3 object(s) in 5.1s: 2 serverside-applied, 1 timed-out
+serverside-applied (2):
+  pod/b -n default serverside-applied (2 attempts, 1.0s)
+  namespace/default serverside-applied (2 attempts, 1.0s)
+timed-out (1):
+  pod/a -n default timed-out (2 attempts, 5.0s): Timed out after 2 attempt(s), last error: boom
+"
+        );
+    }
+
+    #[test]
+    fn list_slowest_objects_of_large_bundles() {
+        let report = Report {
+            objects: (0..30)
+                .map(|i| ObjectReport {
+                    label: format!("pod/{i}"),
+                    action: Some(Action::Apply),
+                    attempts: 2,
+                    duration: Duration::from_secs(i),
+                    ..Default::default()
+                })
+                .collect(),
+        };
+        let summary = format(&report, Duration::from_secs(30));
+        let lines: Vec<_> = summary.lines().collect();
+        assert_eq!(lines[1], "serverside-applied (30, slowest 5):");
+        assert_eq!(lines[2], "  pod/29 serverside-applied (2 attempts, 29.0s)");
+        assert_eq!(lines.len(), 7);
+    }
+}
@@ -0,0 +1,134 @@
+//! Export of traces to an OpenTelemetry collector over OTLP.
+
+use deka::redact::Redactor;
+use miette::{IntoDiagnostic, Result};
+use opentelemetry::{trace::Status, KeyValue, Value};
+use opentelemetry_otlp::{SpanExporter, WithExportConfig};
+use opentelemetry_sdk::{
+    error::OTelSdkResult,
+    trace::{self, SdkTracerProvider, SpanData},
+    Resource,
+};
+use std::{borrow::Cow, time::Duration};
+
+/// Path traces are sent to with OTLP over HTTP.
+const HTTP_TRACES_PATH: &str = "/v1/traces";
+
+#[derive(Clone, Copy, Debug, clap::ValueEnum)]
+pub enum OtlpProtocol {
+    Grpc,
+    Http,
+}
+
+/// Returns a provider of tracers exporting spans in batches to the collector
+/// at `endpoint`. Spans still buffered are only exported by
+/// [`SdkTracerProvider::shutdown`]. Sensitive values are masked from spans.
+pub fn tracer_provider(
+    endpoint: &str,
+    protocol: OtlpProtocol,
+    redactor: Redactor,
+) -> Result<SdkTracerProvider> {
+    let exporter = match protocol {
+        OtlpProtocol::Grpc => SpanExporter::builder()
+            .with_tonic()
+            .with_endpoint(endpoint)
+            .build(),
+        OtlpProtocol::Http => SpanExporter::builder()
+            .with_http()
+            .with_endpoint(traces_endpoint(endpoint))
+            .build(),
+    }
+    .into_diagnostic()?;
+
+    Ok(SdkTracerProvider::builder()
+        .with_batch_exporter(RedactingExporter {
+            inner: exporter,
+            redactor,
+        })
+        .with_resource(
+            Resource::builder()
+                .with_service_name(env!("CARGO_PKG_NAME"))
+                .build(),
+        )
+        .build())
+}
+
+/// Masks the sensitive values learned by a [`Redactor`] in the attributes,
+/// events and status of spans before exporting them.
+#[derive(Debug)]
+struct RedactingExporter<E> {
+    inner: E,
+    redactor: Redactor,
+}
+
+impl<E: trace::SpanExporter> trace::SpanExporter for RedactingExporter<E> {
+    async fn export(&self, mut batch: Vec<SpanData>) -> OTelSdkResult {
+        for span in &mut batch {
+            self.redact_attributes(&mut span.attributes);
+            for event in &mut span.events.events {
+                self.redact_attributes(&mut event.attributes);
+            }
+            if let Status::Error { description } = &mut span.status {
+                if let Cow::Owned(d) = self.redactor.redact_text(description) {
+                    *description = d.into();
+                }
+            }
+        }
+        self.inner.export(batch).await
+    }
+
+    fn shutdown_with_timeout(&self, timeout: Duration) -> OTelSdkResult {
+        self.inner.shutdown_with_timeout(timeout)
+    }
+
+    fn force_flush(&self) -> OTelSdkResult {
+        self.inner.force_flush()
+    }
+
+    fn set_resource(&mut self, resource: &Resource) {
+        self.inner.set_resource(resource)
+    }
+}
+
+impl<E> RedactingExporter<E> {
+    fn redact_attributes(&self, attributes: &mut [KeyValue]) {
+        for kv in attributes {
+            if let Value::String(s) = &kv.value {
+                if let Cow::Owned(v) = self.redactor.redact_text(s.as_str()) {
+                    kv.value = Value::String(v.into());
+                }
+            }
+        }
+    }
+}
+
+/// Completes the endpoint of a collector with the path traces are sent to
+/// over HTTP, unless it already has a path.
+fn traces_endpoint(endpoint: &str) -> String {
+    let base = endpoint.trim_end_matches('/');
+    match base.split_once("://") {
+        Some((_, rest)) if rest.contains('/') => endpoint.to_string(),
+        _ => format!("{}{}", base, HTTP_TRACES_PATH),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn complete_http_endpoint() {
+        assert_eq!(
+            traces_endpoint("http://localhost:4318"),
+            "http://localhost:4318/v1/traces"
+        );
+        assert_eq!(
+            traces_endpoint("http://localhost:4318/"),
+            "http://localhost:4318/v1/traces"
+        );
+        assert_eq!(
+            traces_endpoint("https://otel.example.com/custom/traces"),
+            "https://otel.example.com/custom/traces"
+        );
+    }
+}
@@ -0,0 +1,147 @@
+//! Circuit breaker pausing all attempts while the API server is unavailable,
+//! instead of having every object burn through its retries.
+
+use crate::FailureCause;
+use kube::Client;
+use std::{sync::Mutex, time::Duration};
+use tokio::sync::watch;
+use tracing::{debug, info, warn};
+
+/// How often to check whether the API server is ready again, once tripped.
+pub(crate) const PROBE_INTERVAL: Duration = Duration::from_secs(2);
+
+pub(crate) struct Breaker {
+    /// Number of consecutive failures caused by the API server being
+    /// unavailable that trips the breaker.
+    threshold: usize,
+    failures: Mutex<usize>,
+    /// Whether the breaker tripped, i.e. attempts are paused.
+    open: watch::Sender<bool>,
+}
+
+impl Breaker {
+    pub fn new(threshold: usize) -> Self {
+        Self {
+            threshold,
+            failures: Mutex::new(0),
+            open: watch::Sender::new(false),
+        }
+    }
+
+    /// Waits until attempts are allowed.
+    pub async fn closed(&self) {
+        let _ = self.open.subscribe().wait_for(|open| !open).await;
+    }
+
+    /// Records the outcome of an attempt, `None` meaning success.
+    pub fn record(&self, cause: Option<FailureCause>) {
+        let mut failures = self.failures.lock().unwrap();
+        if cause != Some(FailureCause::Unavailable) {
+            *failures = 0;
+            return;
+        }
+
+        *failures += 1;
+        if *failures >= self.threshold {
+            self.open.send_if_modified(|open| {
+                if *open {
+                    return false;
+                }
+                warn!(
+                    failures = *failures,
+                    "API server unavailable, pausing all attempts until it is ready"
+                );
+                *open = true;
+                true
+            });
+        }
+    }
+
+    /// Probes the API server while the breaker is tripped, and resets it once
+    /// the server responds without a server error. Responses like 401 or 403,
+    /// e.g. from an authenticating proxy in front of `/readyz`, still show
+    /// that the server is reachable. Never returns.
+    pub async fn probe(&self, client: &Client) {
+        let mut open = self.open.subscribe();
+        loop {
+            let _ = open.wait_for(|open| *open).await;
+            loop {
+                tokio::time::sleep(PROBE_INTERVAL).await;
+                let readyz = http::Request::get("/readyz").body(vec![]).unwrap();
+                match client.request_text(readyz).await {
+                    Ok(_) => break,
+                    Err(kube::Error::Api(e)) if e.code < 500 => {
+                        debug!(error = %e, "API server reachable, though not probed");
+                        break;
+                    }
+                    Err(e) => debug!(error = %e, "API server not ready"),
+                }
+            }
+
+            *self.failures.lock().unwrap() = 0;
+            self.open.send_replace(false);
+            info!("API server reachable, resuming attempts");
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock::with_mock_service;
+    use futures::FutureExt;
+    use http::{Request, Response, StatusCode};
+    use kube::client::Body;
+
+    #[test]
+    fn trip_after_consecutive_unavailability() {
+        let breaker = Breaker::new(2);
+
+        breaker.record(Some(FailureCause::Unavailable));
+        breaker.record(Some(FailureCause::MissingKind));
+        breaker.record(Some(FailureCause::Unavailable));
+        assert!(breaker.closed().now_or_never().is_some());
+
+        breaker.record(Some(FailureCause::Unavailable));
+        assert!(breaker.closed().now_or_never().is_none());
+    }
+
+    #[test]
+    fn reset_on_success() {
+        let breaker = Breaker::new(2);
+
+        breaker.record(Some(FailureCause::Unavailable));
+        breaker.record(None);
+        breaker.record(Some(FailureCause::Unavailable));
+        assert!(breaker.closed().now_or_never().is_some());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn reset_once_reachable() {
+        let readyz = |status| {
+            (
+                Request::get("/readyz").body(Body::empty()).unwrap(),
+                Response::builder()
+                    .status(status)
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+        };
+        let expectations = vec![
+            readyz(StatusCode::SERVICE_UNAVAILABLE),
+            readyz(StatusCode::FORBIDDEN),
+        ];
+
+        with_mock_service(expectations, |s| async {
+            let client = Client::new(s, "default");
+            let breaker = Breaker::new(1);
+            breaker.record(Some(FailureCause::Unavailable));
+            tokio::select! {
+                _ = breaker.closed() => {}
+                _ = breaker.probe(&client) => unreachable!(),
+            }
+            assert_eq!(*breaker.failures.lock().unwrap(), 0);
+        })
+        .await;
+    }
+}
@@ -0,0 +1,326 @@
+//! Limiting of the number of concurrent requests made to the API server,
+//! adapting to how well it copes with them.
+
+use futures::future::BoxFuture;
+use http::{Request, Response, StatusCode};
+use std::{
+    collections::VecDeque,
+    sync::{Arc, Mutex},
+    task::{ready, Context, Poll},
+    time::Duration,
+};
+use tokio::{
+    sync::{OwnedSemaphorePermit, Semaphore},
+    time::Instant,
+};
+use tokio_util::sync::PollSemaphore;
+use tower::{Layer, Service};
+use tracing::debug;
+
+/// The limit never goes below this many concurrent requests.
+const MIN_LIMIT: usize = 1;
+
+/// Factor applied to the limit when the API server shows signs of overload.
+const DECREASE_FACTOR: f64 = 0.5;
+
+/// Number of successful requests whose latency is looked at together, so
+/// that a few slow ones do not decrease the limit.
+const LATENCY_WINDOW: usize = 20;
+
+/// Percentile of the latencies of a window compared to the usual latency.
+const LATENCY_PERCENTILE: f64 = 0.5;
+
+/// The latency of a window is considered degraded when its percentile is this
+/// many times the usual latency.
+const LATENCY_TOLERANCE: f64 = 2.0;
+
+/// Weight of each window in the usual latency.
+const LATENCY_SMOOTHING: f64 = 0.1;
+
+/// Limits the number of concurrent requests of a client using AIMD (additive
+/// increase, multiplicative decrease), in the manner of TCP congestion
+/// control.
+///
+/// The limit starts at an initial value. It is halved when a response is a
+/// `429 Too Many Requests` or a 5xx, when a request fails to get a response
+/// at all, or when responses take much longer than usual over a window of
+/// requests. It grows by one every time as many requests as the limit
+/// succeed, up to a maximum.
+#[derive(Clone, Debug)]
+pub struct AdaptiveConcurrencyLayer {
+    limiter: Arc<Limiter>,
+}
+
+impl AdaptiveConcurrencyLayer {
+    /// Creates a layer allowing `initial` concurrent requests at first, and
+    /// up to `max` while the API server copes with them.
+    pub fn new(initial: usize, max: usize) -> Self {
+        let initial = initial.max(MIN_LIMIT);
+        let max = max.max(initial);
+        Self {
+            limiter: Arc::new(Limiter {
+                semaphore: Arc::new(Semaphore::new(initial)),
+                state: Mutex::new(State::new(initial, max)),
+            }),
+        }
+    }
+
+    /// Returns the current number of concurrent requests allowed.
+    pub fn limit(&self) -> usize {
+        self.limiter.state.lock().unwrap().permits
+    }
+}
+
+impl<S> Layer<S> for AdaptiveConcurrencyLayer {
+    type Service = AdaptiveConcurrency<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        AdaptiveConcurrency {
+            inner,
+            limiter: Arc::clone(&self.limiter),
+            semaphore: PollSemaphore::new(Arc::clone(&self.limiter.semaphore)),
+            permit: None,
+        }
+    }
+}
+
+/// Service created by [`AdaptiveConcurrencyLayer`].
+pub struct AdaptiveConcurrency<S> {
+    inner: S,
+    limiter: Arc<Limiter>,
+    semaphore: PollSemaphore,
+    permit: Option<OwnedSemaphorePermit>,
+}
+
+impl<S, ReqBody, ResBody> Service<Request<ReqBody>> for AdaptiveConcurrency<S>
+where
+    S: Service<Request<ReqBody>, Response = Response<ResBody>>,
+    S::Future: Send + 'static,
+{
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = BoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        if self.permit.is_none() {
+            let permit = ready!(self.semaphore.poll_acquire(cx));
+            self.permit = Some(permit.expect("semaphore should never be closed"));
+        }
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, req: Request<ReqBody>) -> Self::Future {
+        let permit = self
+            .permit
+            .take()
+            .expect("poll_ready should be called before call");
+        let limiter = Arc::clone(&self.limiter);
+        let started = Instant::now();
+        let resp = self.inner.call(req);
+        Box::pin(async move {
+            let resp = resp.await;
+            let overloaded = match &resp {
+                Ok(r) => {
+                    r.status() == StatusCode::TOO_MANY_REQUESTS || r.status().is_server_error()
+                }
+                Err(_) => true,
+            };
+            limiter.record(started, overloaded);
+            limiter.release(permit);
+            resp
+        })
+    }
+}
+
+#[derive(Debug)]
+struct Limiter {
+    semaphore: Arc<Semaphore>,
+    state: Mutex<State>,
+}
+
+impl Limiter {
+    fn record(&self, started: Instant, overloaded: bool) {
+        let mut state = self.state.lock().unwrap();
+        let before = state.permits;
+        state.record(started, Instant::now(), overloaded);
+
+        if state.permits > before {
+            self.semaphore.add_permits(state.permits - before);
+        } else if state.permits < before {
+            let excess = before - state.permits;
+            state.debt += excess - self.semaphore.forget_permits(excess);
+            debug!(limit = state.permits, "Decreased concurrency limit");
+        }
+    }
+
+    /// Gives a permit back, unless the limit decreased while it was in use.
+    fn release(&self, permit: OwnedSemaphorePermit) {
+        let mut state = self.state.lock().unwrap();
+        if state.debt > 0 {
+            state.debt -= 1;
+            permit.forget();
+        }
+    }
+}
+
+#[derive(Debug)]
+struct State {
+    limit: f64,
+    max: usize,
+    /// Permits backing the limit, i.e. the limit rounded down.
+    permits: usize,
+    /// Permits in use to forget once released, because the limit decreased.
+    debt: usize,
+    /// Latencies of the successful requests of the current window.
+    window: VecDeque<Duration>,
+    /// Moving average of the latency percentile of past windows.
+    latency: Option<Duration>,
+    /// Overload signals of requests started before the last decrease are
+    /// ignored, as the decrease already accounts for them.
+    last_decrease: Instant,
+}
+
+impl State {
+    fn new(initial: usize, max: usize) -> Self {
+        Self {
+            limit: initial as f64,
+            max,
+            permits: initial,
+            debt: 0,
+            window: VecDeque::with_capacity(LATENCY_WINDOW),
+            latency: None,
+            last_decrease: Instant::now(),
+        }
+    }
+
+    fn record(&mut self, started: Instant, now: Instant, overloaded: bool) {
+        let degraded = !overloaded && self.record_latency(now - started);
+
+        if overloaded || degraded {
+            if started >= self.last_decrease {
+                self.limit = (self.limit * DECREASE_FACTOR).max(MIN_LIMIT as f64);
+                self.last_decrease = now;
+            }
+        } else {
+            self.limit = (self.limit + 1.0 / self.limit).min(self.max as f64);
+        }
+        self.permits = self.limit as usize;
+    }
+
+    /// Adds the latency of a successful request to the window, and tells
+    /// whether the window it completes took much longer than usual.
+    fn record_latency(&mut self, latency: Duration) -> bool {
+        self.window.push_back(latency);
+        if self.window.len() < LATENCY_WINDOW {
+            return false;
+        }
+        let mut window: Vec<_> = self.window.drain(..).collect();
+        window.sort();
+        let index = ((window.len() - 1) as f64 * LATENCY_PERCENTILE).round() as usize;
+        let percentile = window[index];
+
+        let degraded = self.latency.is_some_and(|usual| {
+            percentile.as_secs_f64() > usual.as_secs_f64() * LATENCY_TOLERANCE
+        });
+        self.latency = Some(match self.latency {
+            Some(usual) => {
+                usual.mul_f64(1.0 - LATENCY_SMOOTHING) + percentile.mul_f64(LATENCY_SMOOTHING)
+            }
+            None => percentile,
+        });
+        degraded
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tower_test::mock;
+
+    #[tokio::test(start_paused = true)]
+    async fn decrease_on_overload_and_increase_on_success() {
+        let start = Instant::now();
+        let mut state = State::new(8, 8);
+
+        state.record(start, start + Duration::from_millis(1), true);
+        assert_eq!(state.permits, 4);
+
+        // Started before the decrease, already accounted for.
+        state.record(start, start + Duration::from_millis(2), true);
+        assert_eq!(state.permits, 4);
+
+        let later = start + Duration::from_secs(1);
+        for _ in 0..5 {
+            state.record(later, later, false);
+        }
+        assert_eq!(state.permits, 5);
+        for _ in 0..100 {
+            state.record(later, later, false);
+        }
+        assert_eq!(state.permits, 8);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn decrease_on_sustained_latency() {
+        let start = Instant::now();
+        let mut state = State::new(8, 8);
+        let record = |state: &mut State, ms| {
+            state.record(start, start + Duration::from_millis(ms), false);
+        };
+
+        for _ in 0..LATENCY_WINDOW {
+            record(&mut state, 100);
+        }
+        assert_eq!(state.permits, 8);
+
+        // A few slow requests are not enough.
+        for i in 0..LATENCY_WINDOW {
+            record(&mut state, if i % 4 == 0 { 1000 } else { 100 });
+        }
+        assert_eq!(state.permits, 8);
+
+        for _ in 0..LATENCY_WINDOW {
+            record(&mut state, 500);
+        }
+        assert_eq!(state.permits, 4);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn increase_up_to_max() {
+        let start = Instant::now();
+        let mut state = State::new(2, 4);
+        for _ in 0..100 {
+            state.record(start, start, false);
+        }
+        assert_eq!(state.permits, 4);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn enforce_decreased_limit() {
+        let layer = AdaptiveConcurrencyLayer::new(2, 2);
+        let (mut service, mut handle) =
+            mock::spawn_layer::<Request<()>, Response<()>, _>(layer.clone());
+
+        assert!(matches!(service.poll_ready(), Poll::Ready(Ok(()))));
+        let first = service.call(Request::new(()));
+        assert!(matches!(service.poll_ready(), Poll::Ready(Ok(()))));
+        let second = service.call(Request::new(()));
+        assert!(service.poll_ready().is_pending());
+
+        let (_, send) = handle.next_request().await.unwrap();
+        send.send_response(
+            Response::builder()
+                .status(StatusCode::TOO_MANY_REQUESTS)
+                .body(())
+                .unwrap(),
+        );
+        first.await.unwrap();
+        assert_eq!(layer.limit(), 1);
+        assert!(service.poll_ready().is_pending());
+
+        let (_, send) = handle.next_request().await.unwrap();
+        send.send_response(Response::new(()));
+        second.await.unwrap();
+        assert!(matches!(service.poll_ready(), Poll::Ready(Ok(()))));
+    }
+}
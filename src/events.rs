@@ -0,0 +1,305 @@
+//! Triggers used to retry objects as soon as the dependency they are missing
+//! may have become available, instead of waiting for their backoff to elapse.
+
+use crate::FailureCause;
+use futures::{Stream, StreamExt};
+use k8s_openapi::{
+    api::core::v1::Namespace,
+    apiextensions_apiserver::pkg::apis::apiextensions::v1::CustomResourceDefinition,
+};
+use kube::{
+    api::DynamicObject,
+    runtime::{watcher, WatchStreamExt},
+    Api, Client, ResourceExt,
+};
+use std::{collections::HashSet, time::Duration};
+use tokio::sync::watch;
+use tracing::{debug, warn};
+
+/// Notifies objects waiting for a retry when something they may depend on
+/// happened.
+pub(crate) struct Triggers {
+    /// A CustomResourceDefinition became established.
+    kinds: watch::Sender<()>,
+    /// A Namespace became active.
+    namespaces: watch::Sender<()>,
+}
+
+impl Triggers {
+    pub fn new() -> Self {
+        Self {
+            kinds: watch::Sender::new(()),
+            namespaces: watch::Sender::new(()),
+        }
+    }
+
+    /// Returns a listener that only wakes up on triggers fired after this call.
+    pub fn subscribe(&self) -> Listener {
+        Listener {
+            kinds: self.kinds.subscribe(),
+            namespaces: self.namespaces.subscribe(),
+        }
+    }
+
+    /// Fires the triggers relevant to an object of the input being applied.
+    pub fn object_applied(&self, object: &DynamicObject) {
+        if object
+            .types
+            .as_ref()
+            .is_some_and(|t| t.api_version == "v1" && t.kind == "Namespace")
+        {
+            self.namespaces.send_replace(());
+        }
+    }
+
+    /// Watches the cluster for CustomResourceDefinitions becoming established
+    /// and Namespaces becoming active, firing the corresponding triggers.
+    /// Never returns.
+    pub async fn watch_cluster(&self, client: &Client) {
+        let crds = became_ready(
+            Api::<CustomResourceDefinition>::all(client.clone()),
+            is_established,
+        )
+        .for_each(|crd| async move {
+            debug!(crd = crd.name_any(), "CustomResourceDefinition established");
+            self.kinds.send_replace(());
+        });
+        let namespaces = became_ready(Api::<Namespace>::all(client.clone()), is_active).for_each(
+            |ns| async move {
+                debug!(namespace = ns.name_any(), "Namespace active");
+                self.namespaces.send_replace(());
+            },
+        );
+
+        futures::join!(crds, namespaces);
+        futures::future::pending::<()>().await
+    }
+}
+
+/// Returns a stream of objects as they become ready, logging and recovering
+/// from errors. Objects already ready when the watch starts are ignored, and
+/// so are later modifications of ready objects, e.g. of their labels.
+fn became_ready<K>(api: Api<K>, is_ready: fn(&K) -> bool) -> impl Stream<Item = K>
+where
+    K: kube::Resource + Clone + serde::de::DeserializeOwned + std::fmt::Debug + Send + 'static,
+{
+    watcher(api, watcher::Config::default())
+        .default_backoff()
+        .filter_map(|r| async move {
+            r.inspect_err(|e| warn!(error = %e, "Failed to watch cluster"))
+                .ok()
+        })
+        .scan(Readiness::default(), move |readiness, event| {
+            futures::future::ready(Some(readiness.update(event, is_ready)))
+        })
+        .filter_map(futures::future::ready)
+}
+
+/// Names of the objects known to be ready, to tell when others become ready.
+#[derive(Default)]
+struct Readiness {
+    ready: HashSet<String>,
+    /// Objects found ready while the watch (re)starts.
+    listed: HashSet<String>,
+    /// Whether the initial list of objects is complete.
+    initialized: bool,
+}
+
+impl Readiness {
+    /// Returns the object of `event` if it just became ready.
+    fn update<K: kube::Resource>(
+        &mut self,
+        event: watcher::Event<K>,
+        is_ready: fn(&K) -> bool,
+    ) -> Option<K> {
+        match event {
+            watcher::Event::Init => {
+                self.listed.clear();
+                None
+            }
+            watcher::Event::InitApply(o) => {
+                if !is_ready(&o) {
+                    return None;
+                }
+                self.listed.insert(o.name_any());
+                // Objects that became ready while the watch was restarting.
+                (self.initialized && !self.ready.contains(&o.name_any())).then_some(o)
+            }
+            watcher::Event::InitDone => {
+                self.ready = std::mem::take(&mut self.listed);
+                self.initialized = true;
+                None
+            }
+            watcher::Event::Apply(o) if is_ready(&o) => {
+                self.ready.insert(o.name_any()).then_some(o)
+            }
+            watcher::Event::Apply(o) | watcher::Event::Delete(o) => {
+                self.ready.remove(&o.name_any());
+                None
+            }
+        }
+    }
+}
+
+fn is_established(crd: &CustomResourceDefinition) -> bool {
+    crd.status
+        .as_ref()
+        .and_then(|s| s.conditions.as_ref())
+        .into_iter()
+        .flatten()
+        .any(|c| c.type_ == "Established" && c.status == "True")
+}
+
+fn is_active(ns: &Namespace) -> bool {
+    ns.status
+        .as_ref()
+        .is_some_and(|s| s.phase.as_deref() == Some("Active"))
+}
+
+pub(crate) struct Listener {
+    kinds: watch::Receiver<()>,
+    namespaces: watch::Receiver<()>,
+}
+
+impl Listener {
+    /// Ignores the triggers fired so far. Should be called before each attempt
+    /// so that [`Listener::wait`] only considers what happened since then.
+    pub fn mark_seen(&mut self) {
+        self.kinds.mark_unchanged();
+        self.namespaces.mark_unchanged();
+    }
+
+    /// Waits for `delay` to elapse, or for the trigger relevant to `cause` to
+    /// fire, whichever comes first. Only missing kinds and namespaces have a
+    /// trigger, other failures are not known to be resolved by anything the
+    /// cluster or the input does, so they only wait for `delay`.
+    pub async fn wait(&mut self, cause: FailureCause, delay: Duration) {
+        let trigger = match cause {
+            FailureCause::MissingKind => &mut self.kinds,
+            FailureCause::MissingNamespace => &mut self.namespaces,
+            _ => return tokio::time::sleep(delay).await,
+        };
+
+        tokio::select! {
+            _ = tokio::time::sleep(delay) => {}
+            Ok(_) = trigger.changed() => {
+                debug!(cause = cause.as_ref(), "Retrying early, dependency may be available");
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn namespace() -> DynamicObject {
+        serde_json::from_value(json!({
+            "apiVersion": "v1",
+            "kind": "Namespace",
+            "metadata": { "name": "example" },
+        }))
+        .unwrap()
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn wait_until_delay_without_trigger() {
+        let triggers = Triggers::new();
+        let mut listener = triggers.subscribe();
+        let start = tokio::time::Instant::now();
+
+        listener
+            .wait(FailureCause::MissingNamespace, Duration::from_secs(30))
+            .await;
+        assert_eq!(start.elapsed(), Duration::from_secs(30));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn wake_up_on_relevant_trigger() {
+        let triggers = Triggers::new();
+        let mut listener = triggers.subscribe();
+        let start = tokio::time::Instant::now();
+
+        listener.mark_seen();
+        triggers.object_applied(&namespace());
+        listener
+            .wait(FailureCause::MissingNamespace, Duration::from_secs(30))
+            .await;
+        assert_eq!(start.elapsed(), Duration::ZERO);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn ignore_irrelevant_and_seen_triggers() {
+        let triggers = Triggers::new();
+        let mut listener = triggers.subscribe();
+        let start = tokio::time::Instant::now();
+
+        triggers.object_applied(&namespace());
+        listener.mark_seen();
+        triggers.kinds.send_replace(());
+        listener
+            .wait(FailureCause::MissingNamespace, Duration::from_secs(30))
+            .await;
+        assert_eq!(start.elapsed(), Duration::from_secs(30));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn only_wake_up_missing_dependencies() {
+        let triggers = Triggers::new();
+        let mut listener = triggers.subscribe();
+        let start = tokio::time::Instant::now();
+
+        listener.mark_seen();
+        triggers.object_applied(&namespace());
+        listener
+            .wait(FailureCause::Rejected, Duration::from_secs(30))
+            .await;
+        assert_eq!(start.elapsed(), Duration::from_secs(30));
+    }
+
+    #[test]
+    fn trigger_on_transition_to_ready() {
+        let ns = |name: &str, phase: &str| -> Namespace {
+            serde_json::from_value(json!({
+                "apiVersion": "v1",
+                "kind": "Namespace",
+                "metadata": { "name": name },
+                "status": { "phase": phase },
+            }))
+            .unwrap()
+        };
+        let mut readiness = Readiness::default();
+        let mut update = |event| readiness.update(event, is_active).map(|o| o.name_any());
+
+        assert_eq!(update(watcher::Event::Init), None);
+        assert_eq!(update(watcher::Event::InitApply(ns("old", "Active"))), None);
+        assert_eq!(update(watcher::Event::InitDone), None);
+        assert_eq!(update(watcher::Event::Apply(ns("old", "Active"))), None);
+
+        assert_eq!(
+            update(watcher::Event::Apply(ns("new", "Terminating"))),
+            None
+        );
+        assert_eq!(
+            update(watcher::Event::Apply(ns("new", "Active"))).as_deref(),
+            Some("new")
+        );
+        assert_eq!(update(watcher::Event::Apply(ns("new", "Active"))), None);
+
+        // Became ready while the watch restarted.
+        assert_eq!(update(watcher::Event::Init), None);
+        assert_eq!(update(watcher::Event::InitApply(ns("new", "Active"))), None);
+        assert_eq!(
+            update(watcher::Event::InitApply(ns("other", "Active"))).as_deref(),
+            Some("other")
+        );
+        assert_eq!(update(watcher::Event::InitDone), None);
+
+        assert_eq!(update(watcher::Event::Delete(ns("new", "Active"))), None);
+        assert_eq!(
+            update(watcher::Event::Apply(ns("new", "Active"))).as_deref(),
+            Some("new")
+        );
+    }
+}
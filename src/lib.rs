@@ -1,7 +1,21 @@
+pub mod analysis;
 pub mod backoff;
-
-use ::backoff as backoffcrate;
-use backoff::{Backoff, BackoffWrapper};
+mod breaker;
+pub mod concurrency;
+mod events;
+pub mod metrics;
+#[cfg(test)]
+mod mock;
+pub mod overrides;
+mod progress;
+pub mod rate_limit;
+pub mod redact;
+pub mod report;
+pub mod throttling;
+
+use backoff::{Backoff, Either};
+use breaker::Breaker;
+use events::{Listener, Triggers};
 use futures::StreamExt;
 use kube::{
     api::{DeleteParams, DynamicObject, Patch, PatchParams},
@@ -10,27 +24,120 @@ use kube::{
     error::DiscoveryError,
     Api, Client, Error as KubeError, Resource, ResourceExt,
 };
+use progress::{Progress, Tracker};
+use report::{ObjectReport, Report};
 use std::{
+    collections::HashMap,
     str::FromStr,
     sync::{Arc, Mutex},
+    time::Duration,
 };
 use strum_macros::{AsRefStr, EnumString};
 use thiserror::Error;
-use tracing::{debug_span, info, instrument, warn, Instrument, Span};
+use tokio::sync::mpsc::UnboundedSender;
+use tokio_util::sync::CancellationToken;
+use tracing::{debug_span, error, field, info, info_span, instrument, warn, Instrument, Span};
 
 const ANNOTATION_ACTION: &str = "deka.ndrpnt.dev/action";
+const ANNOTATION_TIMEOUT: &str = "deka.ndrpnt.dev/timeout";
+const ANNOTATION_RUN_ID: &str = "deka.ndrpnt.dev/run-id";
 
-#[derive(EnumString, PartialEq, Default, AsRefStr)]
+/// What to do with an object, as set by the `deka.ndrpnt.dev/action`
+/// annotation.
+#[derive(EnumString, PartialEq, Default, AsRefStr, Clone, Copy, Debug)]
 #[strum(serialize_all = "kebab-case")]
-enum Action {
+pub enum Action {
     #[default]
     Apply,
     Delete,
 }
 
+/// Options of [`apply_objects`].
+#[derive(Clone, Debug, Default)]
+pub struct ApplyParams {
+    /// Retry objects that failed because of a missing kind or namespace as
+    /// soon as it may have become available, i.e. when a
+    /// CustomResourceDefinition becomes established or a Namespace becomes
+    /// active, instead of waiting for the backoff to elapse. Requires permission to
+    /// watch CustomResourceDefinitions and Namespaces.
+    pub event_driven: bool,
+
+    /// Give up on all remaining objects when none succeeded for this long while
+    /// each of them failed at least once, rather than retrying them until their
+    /// backoff expires.
+    pub stall_timeout: Option<Duration>,
+
+    /// Give up on all remaining objects, including in-flight attempts, once
+    /// this long has elapsed since the start.
+    pub deadline: Option<Duration>,
+
+    /// Give up on an object once this long has elapsed since its first
+    /// attempt. Can be overridden per object by the `deka.ndrpnt.dev/timeout`
+    /// annotation, e.g. `90s` or `5m`, where `0` means no limit.
+    pub object_timeout: Option<Duration>,
+
+    /// Stop starting new attempts once cancelled. Objects are given up on as
+    /// soon as their in-flight attempt, if any, completes.
+    pub cancel: CancellationToken,
+
+    /// How long to wait for in-flight attempts to complete after `cancel` is
+    /// cancelled, before giving up on them too. Waits indefinitely if unset.
+    pub grace_period: Option<Duration>,
+
+    /// Pause all attempts once this many consecutive ones failed because the
+    /// API server is unavailable, i.e. on connection errors and 5xx responses,
+    /// until its `/readyz` endpoint responds without a 5xx again.
+    pub breaker_threshold: Option<usize>,
+
+    /// Retry settings of the objects of some kinds, overriding the backoff
+    /// and `object_timeout`. The first matching override applies.
+    pub overrides: Vec<overrides::KindOverride>,
+
+    /// Policy the backoff was built from, which the retry policies of
+    /// `overrides` inherit the unset fields of.
+    pub retry: backoff::RetryPolicy,
+
+    /// Receives what happens to each object as it happens, e.g. to display
+    /// progress.
+    pub events: Option<UnboundedSender<report::Event>>,
+
+    /// Keep the objects as persisted by the API server once applied, in
+    /// [`ObjectReport::applied`](report::ObjectReport::applied).
+    pub keep_applied: bool,
+
+    /// Records the outcome of objects in these metrics.
+    pub metrics: Option<metrics::Metrics>,
+
+    /// Stamp applied objects with this identifier of the run as the
+    /// `deka.ndrpnt.dev/run-id` annotation, to tell which run last applied
+    /// them.
+    pub run_id: Option<String>,
+
+    /// Scope of the kinds of the objects, e.g. as resolved by
+    /// [`analysis::resolve_kinds`], to identify objects of cluster-scoped
+    /// kinds without a namespace from the start. Other kinds are assumed to
+    /// be namespaced until discovered.
+    pub scopes: HashMap<GroupVersionKind, Scope>,
+
+    /// Layer pausing the client when throttled, to retry throttled objects
+    /// once the API server allows it rather than after their own backoff.
+    pub throttling: Option<throttling::RetryAfterLayer>,
+}
+
 #[derive(Error, Debug)]
 #[error("Error(s) while applying objects")]
-pub struct ApplyErrors(Vec<ApplyError>);
+pub struct ApplyErrors(Report);
+
+impl ApplyErrors {
+    /// Returns what happened to each object, including successful ones.
+    pub fn report(&self) -> &Report {
+        &self.0
+    }
+
+    pub fn into_report(self) -> Report {
+        self.0
+    }
+}
 
 #[derive(Error, Debug)]
 pub enum ApplyError {
@@ -45,6 +152,125 @@ pub enum ApplyError {
 
     #[error("StrumParseError: {0}")]
     StrumParse(#[from] strum::ParseError),
+
+    #[error("ParseDurationError: {0}")]
+    ParseDuration(#[from] ParseDurationError),
+
+    #[error("Stalled after {attempts} attempt(s), last error: {last_error}")]
+    Stalled { attempts: usize, last_error: String },
+
+    #[error("Deadline exceeded after {attempts} attempt(s), last error: {last_error}")]
+    DeadlineExceeded { attempts: usize, last_error: String },
+
+    #[error("Timed out after {attempts} attempt(s), last error: {last_error}")]
+    TimedOut { attempts: usize, last_error: String },
+
+    #[error("Cancelled after {attempts} attempt(s), last error: {last_error}")]
+    Cancelled { attempts: usize, last_error: String },
+}
+
+#[derive(Error, Debug)]
+#[error("Invalid duration: {0:?}")]
+pub struct ParseDurationError(String);
+
+/// Why an attempt to apply or delete an object failed.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord, AsRefStr)]
+#[strum(serialize_all = "kebab-case")]
+pub enum FailureCause {
+    /// The kind of the object is not served by the API server (yet).
+    MissingKind,
+    /// The namespace of the object does not exist (yet).
+    MissingNamespace,
+    /// The API server asked to slow down (HTTP 429).
+    Throttled,
+    /// The API server could not be reached or failed to handle the request
+    /// (connection error, HTTP 5xx).
+    Unavailable,
+    /// The API server refused the credentials, or they do not allow the
+    /// request (HTTP 401, 403).
+    Unauthorized,
+    /// The API server refused the request as is (HTTP 4xx), e.g. because the
+    /// object is invalid or denied by an admission webhook.
+    Rejected,
+    Other,
+}
+
+impl FailureCause {
+    /// Classifies an error that is not known to be caused by a missing
+    /// dependency.
+    fn of(error: &KubeError) -> Self {
+        match error {
+            KubeError::Api(r) if r.code == 429 => Self::Throttled,
+            KubeError::Api(r) if r.code >= 500 => Self::Unavailable,
+            KubeError::Api(r) if r.code == 401 || r.code == 403 => Self::Unauthorized,
+            KubeError::Api(r) if r.code >= 400 => Self::Rejected,
+            KubeError::HyperError(_) | KubeError::Service(_) => Self::Unavailable,
+            _ => Self::Other,
+        }
+    }
+}
+
+/// A failed attempt to apply or delete an object.
+struct Failure {
+    error: KubeError,
+    cause: FailureCause,
+}
+
+impl Failure {
+    fn new(error: KubeError, cause: FailureCause) -> Self {
+        Self { error, cause }
+    }
+}
+
+/// Hooks through which [`apply_object`] interacts with the other objects being
+/// applied.
+#[derive(Default)]
+struct ObjectContext<'a> {
+    listener: Option<Listener>,
+    tracker: Option<Tracker<'a>>,
+    breaker: Option<&'a Breaker>,
+    overrides: &'a [overrides::KindOverride],
+    retry: Option<&'a backoff::RetryPolicy>,
+    timeout: Option<Duration>,
+    cancel: CancellationToken,
+    run_id: Option<&'a str>,
+    throttling: Option<&'a throttling::RetryAfterLayer>,
+}
+
+/// Why [`apply_objects`] gave up on the remaining objects.
+enum Interruption {
+    Stalled(Duration),
+    DeadlineExceeded(Duration),
+    Cancelled(Duration),
+}
+
+impl Interruption {
+    fn error(&self, attempts: usize, last_error: String) -> ApplyError {
+        match self {
+            Self::Stalled(_) => ApplyError::Stalled {
+                attempts,
+                last_error,
+            },
+            Self::DeadlineExceeded(_) => ApplyError::DeadlineExceeded {
+                attempts,
+                last_error,
+            },
+            Self::Cancelled(_) => ApplyError::Cancelled {
+                attempts,
+                last_error,
+            },
+        }
+    }
+}
+
+impl std::fmt::Display for Interruption {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Stalled(d) => write!(f, "No progress for {:?}", d),
+            Self::DeadlineExceeded(d) => write!(f, "Deadline of {:?} exceeded", d),
+            Self::Cancelled(d) => write!(f, "Grace period of {:?} elapsed after cancellation", d),
+        }
+    }
 }
 
 #[instrument(skip_all, fields(
@@ -52,6 +278,7 @@ pub enum ApplyError {
     field_manager = manager,
     default_namespace = namespace.unwrap_or(client.default_namespace()),
     objects.error_count,
+    attempts.throttled_count,
 ), err)]
 pub async fn apply_objects<B: Backoff + Clone>(
     objects: Vec<DynamicObject>,
@@ -59,29 +286,166 @@ pub async fn apply_objects<B: Backoff + Clone>(
     manager: &str,
     namespace: Option<&str>,
     backoff: &B,
-) -> Result<(), ApplyErrors> {
-    let errors = Arc::new(Mutex::new(Vec::new()));
-    futures::stream::iter(objects)
-        .for_each_concurrent(None, |obj| {
+    params: &ApplyParams,
+) -> Result<Report, ApplyErrors> {
+    let triggers = &params.event_driven.then(Triggers::new);
+    let breaker = &params.breaker_threshold.map(Breaker::new);
+    let progress = &Progress::new(
+        objects.iter().map(|o| {
+            let identity = analysis::ObjectIdentity::of(o, namespace, client, &params.scopes);
+            let label = match &identity {
+                Some(i) => i.to_string(),
+                None => o.name_any(),
+            };
+            (label, identity)
+        }),
+        params.events.clone(),
+    );
+    let actions: Vec<_> = objects.iter().map(|o| object_action(o).ok()).collect();
+    let errors = Arc::new(Mutex::new(
+        (0..objects.len()).map(|_| None).collect::<Vec<_>>(),
+    ));
+    let applied = Arc::new(Mutex::new(
+        (0..objects.len()).map(|_| None).collect::<Vec<_>>(),
+    ));
+    let apply = futures::stream::iter(objects.into_iter().enumerate()).for_each_concurrent(
+        None,
+        |(i, obj)| {
             let c_errors = Arc::clone(&errors);
+            let c_applied = Arc::clone(&applied);
             async move {
-                if let Err(e) = apply_object(&obj, client, manager, namespace, backoff).await {
-                    c_errors.lock().unwrap().push(e);
+                let ctx = ObjectContext {
+                    listener: triggers.as_ref().map(Triggers::subscribe),
+                    tracker: Some(progress.tracker(i)),
+                    breaker: breaker.as_ref(),
+                    overrides: &params.overrides,
+                    retry: Some(&params.retry),
+                    timeout: params.object_timeout,
+                    cancel: params.cancel.clone(),
+                    run_id: params.run_id.as_deref(),
+                    throttling: params.throttling.as_ref(),
+                };
+                let result = apply_object(&obj, client, manager, namespace, backoff, ctx).await;
+                progress.tracker(i).finished(result.as_ref().err());
+                match result {
+                    Ok(persisted) => {
+                        if let Some(t) = triggers {
+                            t.object_applied(&obj);
+                        }
+                        if params.keep_applied {
+                            c_applied.lock().unwrap()[i] = persisted;
+                        }
+                    }
+                    Err(e) => c_errors.lock().unwrap()[i] = Some(e),
                 }
             }
-        })
-        .await;
+        },
+    );
+    let watch = async {
+        match triggers {
+            Some(t) => t.watch_cluster(client).await,
+            None => futures::future::pending().await,
+        }
+    };
+    let probe = async {
+        match breaker {
+            Some(b) => b.probe(client).await,
+            None => futures::future::pending().await,
+        }
+    };
+    let stall = async {
+        match params.stall_timeout {
+            Some(w) => progress.stalled(w).await,
+            None => futures::future::pending().await,
+        }
+    };
+    let deadline = async {
+        match params.deadline {
+            Some(d) => tokio::time::sleep(d).await,
+            None => futures::future::pending().await,
+        }
+    };
+    let grace = async {
+        params.cancel.cancelled().await;
+        match params.grace_period {
+            Some(g) => tokio::time::sleep(g).await,
+            None => futures::future::pending().await,
+        }
+    };
+
+    let interruption = tokio::select! {
+        _ = apply => None,
+        _ = watch => None,
+        _ = probe => None,
+        _ = stall => params.stall_timeout.map(Interruption::Stalled),
+        _ = deadline => params.deadline.map(Interruption::DeadlineExceeded),
+        _ = grace => params.grace_period.map(Interruption::Cancelled),
+    };
 
-    let errors = Arc::try_unwrap(errors)
+    let mut errors = Arc::try_unwrap(errors)
+        .expect("Arc should have only one reference")
+        .into_inner()
+        .unwrap();
+    let applied = Arc::try_unwrap(applied)
         .expect("Arc should have only one reference")
         .into_inner()
         .unwrap();
-    Span::current().record("objects.error_count", errors.len());
+    if let Some(interruption) = interruption {
+        for (cause, remaining) in progress.remaining() {
+            let labels: Vec<_> = remaining.iter().map(|(_, o)| o.label.as_str()).collect();
+            error!(
+                cause = cause.as_ref().map(|c| c.as_ref()),
+                objects = labels.join(", "),
+                "{}, giving up on {} object(s)",
+                interruption,
+                remaining.len(),
+            );
+            for (i, o) in remaining {
+                errors[i] = Some(
+                    interruption.error(
+                        o.attempts,
+                        o.last_failure()
+                            .map(|f| f.error.clone())
+                            .unwrap_or_default(),
+                    ),
+                );
+            }
+        }
+    }
 
-    if errors.is_empty() {
-        Ok(())
+    let report = Report {
+        objects: progress
+            .objects()
+            .into_iter()
+            .zip(actions)
+            .zip(errors)
+            .zip(applied)
+            .map(|(((o, action), error), applied)| ObjectReport {
+                label: o.label,
+                identity: o.identity,
+                action,
+                attempts: o.attempts,
+                duration: o.duration.unwrap_or_else(|| progress.elapsed()),
+                throttled: o.throttled,
+                failures: o.failures,
+                error,
+                applied,
+            })
+            .collect(),
+    };
+    if let Some(m) = &params.metrics {
+        m.record(&report, progress.elapsed());
+    }
+    Span::current().record("objects.error_count", report.errors().count());
+    Span::current().record(
+        "attempts.throttled_count",
+        report.objects.iter().map(|o| o.throttled).sum::<usize>(),
+    );
+
+    if report.is_success() {
+        Ok(report)
     } else {
-        Err(ApplyErrors(errors))
+        Err(ApplyErrors(report))
     }
 }
 
@@ -99,102 +463,322 @@ async fn apply_object<B: Backoff + Clone>(
     manager: &str,
     namespace: Option<&str>,
     backoff: &B,
-) -> Result<(), ApplyError> {
-    let namespace = object
-        .meta()
-        .namespace
-        .as_deref()
-        .or(namespace)
-        .unwrap_or(client.default_namespace());
+    mut ctx: ObjectContext<'_>,
+) -> Result<Option<DynamicObject>, ApplyError> {
+    let namespace = effective_namespace(object, namespace, client);
     Span::current().record("namespace", namespace);
 
-    let action = &match object.annotations().get(ANNOTATION_ACTION) {
-        Some(a) => Action::from_str(a)?,
-        None => Action::default(),
-    };
+    let action = &object_action(object)?;
     Span::current().record("action", action.as_ref());
 
     let gvk = &GroupVersionKind::try_from(object.types.as_ref().unwrap_or(&TypeMeta::default()))?;
-    let data = &Patch::Apply(serde_json::to_value(&object)?);
+    let mut data = serde_json::to_value(object)?;
+    if let Some(id) = ctx.run_id {
+        data["metadata"]["annotations"][ANNOTATION_RUN_ID] = id.into();
+    }
+    let data = &Patch::Apply(data);
+
+    let kind_override = overrides::find(ctx.overrides, gvk);
+    // A zero timeout means no limit, like the run-wide timeout of the CLI.
+    let timeout = match object.annotations().get(ANNOTATION_TIMEOUT) {
+        Some(t) => Some(parse_duration(t)?),
+        None => kind_override.and_then(|o| o.timeout).or(ctx.timeout),
+    }
+    .filter(|t| !t.is_zero());
+    let mut backoff = match kind_override.and_then(|o| o.retry.as_ref()) {
+        Some(patch) => Either::Right(
+            patch
+                .apply(ctx.retry.unwrap_or(&Default::default()))
+                .build(),
+        ),
+        None => Either::Left(backoff.clone()),
+    };
+
+    let cancel = ctx.cancel.clone();
+    let mut attempts = 0;
+    let mut last_error = None;
+    let retry = async {
+        backoff.reset();
+        let started = tokio::time::Instant::now();
+        loop {
+            if cancel.is_cancelled() {
+                return Err(ApplyError::Cancelled {
+                    attempts,
+                    last_error: last_error.take().unwrap_or_default(),
+                });
+            }
+            if let Some(b) = ctx.breaker {
+                tokio::select! {
+                    _ = b.closed() => {}
+                    _ = cancel.cancelled() => continue,
+                }
+            }
+            if let Some(l) = &mut ctx.listener {
+                l.mark_seen();
+            }
 
-    backoffcrate::future::retry(BackoffWrapper(backoff.clone()), || async move {
-        let (resource, capabilities) = match discovery::pinned_kind(client, gvk)
-            .instrument(debug_span!("discover_api_resource").or_current())
+            attempts += 1;
+            let span = info_span!(
+                "attempt",
+                attempt = attempts,
+                elapsed_ms = started.elapsed().as_millis() as u64,
+                cause = field::Empty,
+                delay_ms = field::Empty,
+            );
+            let failure = match try_apply_object(
+                object,
+                client,
+                manager,
+                namespace,
+                action,
+                gvk,
+                data,
+                ctx.tracker.as_ref(),
+            )
+            .instrument(span.clone())
             .await
-        {
-            Ok(v) => v,
-            Err(KubeError::Discovery(DiscoveryError::MissingKind(_)))
-                if action == &Action::Delete =>
             {
-                info!("Object already deleted (kind not found)");
-                return Ok(());
+                Ok(persisted) => {
+                    if let Some(t) = &ctx.tracker {
+                        t.succeeded();
+                    }
+                    if let Some(b) = ctx.breaker {
+                        b.record(None);
+                    }
+                    return Ok(persisted);
+                }
+                Err(f) => f,
+            };
+            if let Some(t) = &ctx.tracker {
+                t.failed(failure.cause, &failure.error);
             }
-            Err(e) => {
-                warn!(error = %e, "Failed to discover API");
-                return Err(backoffcrate::Error::transient(e));
+            if let Some(b) = ctx.breaker {
+                b.record(Some(failure.cause));
             }
-        };
 
-        let api: Api<DynamicObject> = match capabilities.scope {
-            Scope::Cluster => Api::all_with(client.clone(), &resource),
-            Scope::Namespaced => Api::namespaced_with(client.clone(), namespace, &resource),
-        };
+            span.record("cause", failure.cause.as_ref());
+            let retry_after = ctx
+                .throttling
+                .filter(|_| failure.cause == FailureCause::Throttled)
+                .and_then(|t| t.remaining());
+            let delay = match retry_after {
+                Some(d) => backoff.next_backoff_throttled(attempts, d),
+                None => backoff.next_backoff_after(failure.cause, attempts),
+            };
+            let Some(delay) = delay else {
+                return Err(ApplyError::Kube(failure.error));
+            };
+            span.record("delay_ms", delay.as_millis() as u64);
+            span.in_scope(|| info!("Retrying after backoff"));
+            last_error = Some(failure.error.to_string());
+            let wait = async {
+                match &mut ctx.listener {
+                    Some(l) => l.wait(failure.cause, delay).await,
+                    None => tokio::time::sleep(delay).await,
+                }
+            }
+            .instrument(span);
+            tokio::select! {
+                _ = wait => {}
+                _ = cancel.cancelled() => {}
+            }
+        }
+    };
 
-        match action {
-            Action::Apply => {
-                let params = PatchParams::apply(manager).force();
-                let resp = api
-                    .patch(object.name_any().as_ref(), &params, data)
-                    .instrument(debug_span!("patch").or_current())
-                    .await;
-                match resp {
-                    Ok(_) => {
-                        info!("Applied object");
-                        Ok(())
-                    }
-                    Err(e) => {
-                        warn!(error = %e, "Failed to apply object");
-                        Err(backoffcrate::Error::transient(e))
-                    }
+    match timeout {
+        Some(t) => match tokio::time::timeout(t, retry).await {
+            Ok(r) => r,
+            Err(_) => Err(ApplyError::TimedOut {
+                attempts,
+                last_error: last_error.unwrap_or_default(),
+            }),
+        },
+        None => retry.await,
+    }
+}
+
+/// Makes a single attempt at applying or deleting an object. Returns the
+/// object as persisted by the API server once applied.
+#[allow(clippy::too_many_arguments)]
+async fn try_apply_object(
+    object: &DynamicObject,
+    client: &Client,
+    manager: &str,
+    namespace: &str,
+    action: &Action,
+    gvk: &GroupVersionKind,
+    data: &Patch<serde_json::Value>,
+    tracker: Option<&Tracker<'_>>,
+) -> Result<Option<DynamicObject>, Failure> {
+    let (resource, capabilities) = match discovery::pinned_kind(client, gvk)
+        .instrument(debug_span!("discover_api_resource").or_current())
+        .await
+    {
+        Ok(v) => v,
+        Err(KubeError::Discovery(DiscoveryError::MissingKind(_))) if action == &Action::Delete => {
+            info!("Object already deleted (kind not found)");
+            return Ok(None);
+        }
+        Err(e) => {
+            warn!(error = %e, "Failed to discover API");
+            let cause = match &e {
+                KubeError::Discovery(_) => FailureCause::MissingKind,
+                KubeError::Api(r) if r.code == 404 => FailureCause::MissingKind,
+                e => FailureCause::of(e),
+            };
+            return Err(Failure::new(e, cause));
+        }
+    };
+
+    if let Some(t) = tracker {
+        t.discovered(&capabilities.scope);
+    }
+    let api: Api<DynamicObject> = match capabilities.scope {
+        Scope::Cluster => Api::all_with(client.clone(), &resource),
+        Scope::Namespaced => Api::namespaced_with(client.clone(), namespace, &resource),
+    };
+
+    match action {
+        Action::Apply => {
+            let params = PatchParams::apply(manager).force();
+            let resp = api
+                .patch(object.name_any().as_ref(), &params, data)
+                .instrument(debug_span!("patch").or_current())
+                .await;
+            match resp {
+                Ok(persisted) => {
+                    info!("Applied object");
+                    Ok(Some(persisted))
+                }
+                Err(e) => {
+                    warn!(error = %e, "Failed to apply object");
+                    let cause = match (&e, &capabilities.scope) {
+                        (KubeError::Api(r), Scope::Namespaced) if r.code == 404 => {
+                            FailureCause::MissingNamespace
+                        }
+                        (e, _) => FailureCause::of(e),
+                    };
+                    Err(Failure::new(e, cause))
                 }
             }
-            Action::Delete => {
-                let resp = api
-                    .delete(object.name_any().as_ref(), &DeleteParams::default())
-                    .instrument(debug_span!("delete").or_current())
-                    .await;
-                match resp {
-                    Ok(_) => {
-                        info!("Deleted object");
-                        Ok(())
-                    }
-                    Err(KubeError::Api(e)) if e.code == 404 => {
-                        info!("Object already deleted (not found)");
-                        Ok(())
-                    }
-                    Err(e) => {
-                        warn!(error = %e, "Failed to delete object");
-                        Err(backoffcrate::Error::transient(e))
-                    }
+        }
+        Action::Delete => {
+            let resp = api
+                .delete(object.name_any().as_ref(), &DeleteParams::default())
+                .instrument(debug_span!("delete").or_current())
+                .await;
+            match resp {
+                Ok(_) => {
+                    info!("Deleted object");
+                    Ok(None)
+                }
+                Err(KubeError::Api(e)) if e.code == 404 => {
+                    info!("Object already deleted (not found)");
+                    Ok(None)
+                }
+                Err(e) => {
+                    warn!(error = %e, "Failed to delete object");
+                    let cause = FailureCause::of(&e);
+                    Err(Failure::new(e, cause))
                 }
             }
         }
-    })
-    .await
-    .map_err(ApplyError::Kube)
+    }
+}
+
+/// Resolves the namespace an object is applied to: its own, falling back to
+/// the one given by the caller, and then to the client's default.
+fn effective_namespace<'a>(
+    object: &'a DynamicObject,
+    namespace: Option<&'a str>,
+    client: &'a Client,
+) -> &'a str {
+    object
+        .meta()
+        .namespace
+        .as_deref()
+        .or(namespace)
+        .unwrap_or(client.default_namespace())
+}
+
+/// Parses a duration made of a sequence of decimal numbers, each with a unit
+/// suffix among `ms`, `s`, `m` and `h`, e.g. `300ms` or `1h30m`. A number
+/// without suffix is a number of seconds.
+pub fn parse_duration(s: &str) -> Result<Duration, ParseDurationError> {
+    let err = || ParseDurationError(s.to_string());
+    if s.is_empty() {
+        return Err(err());
+    }
+    if let Ok(secs) = s.parse::<f64>() {
+        return Duration::try_from_secs_f64(secs).map_err(|_| err());
+    }
+
+    let mut total = Duration::ZERO;
+    let mut rest = s;
+    while !rest.is_empty() {
+        let split = rest
+            .find(|c: char| !c.is_ascii_digit() && c != '.')
+            .ok_or_else(err)?;
+        let (value, tail) = rest.split_at(split);
+        let value: f64 = value.parse().map_err(|_| err())?;
+        let unit_len = tail
+            .find(|c: char| c.is_ascii_digit())
+            .unwrap_or(tail.len());
+        let (unit, tail) = tail.split_at(unit_len);
+        let secs = match unit {
+            "ms" => value / 1000.0,
+            "s" => value,
+            "m" => value * 60.0,
+            "h" => value * 3600.0,
+            _ => return Err(err()),
+        };
+        total += Duration::try_from_secs_f64(secs).map_err(|_| err())?;
+        rest = tail;
+    }
+
+    Ok(total)
+}
+
+/// Deserializes a duration in the format of [`parse_duration`].
+pub(crate) fn deserialize_duration<'de, D>(deserializer: D) -> Result<Duration, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    let s = <String as serde::Deserialize>::deserialize(deserializer)?;
+    parse_duration(&s).map_err(serde::de::Error::custom)
+}
+
+/// Deserializes an optional duration in the format of [`parse_duration`].
+pub(crate) fn deserialize_optional_duration<'de, D>(
+    deserializer: D,
+) -> Result<Option<Duration>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    deserialize_duration(deserializer).map(Some)
+}
+
+fn object_action(object: &DynamicObject) -> Result<Action, strum::ParseError> {
+    match object.annotations().get(ANNOTATION_ACTION) {
+        Some(a) => Action::from_str(a),
+        None => Ok(Action::default()),
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::mock::{unwrap_arc_mutex, with_mock_service, Expectation};
     use http::{Request, Response, StatusCode};
     use kube::client::Body;
     use serde_json::{json, Value};
-    use std::cell::LazyCell;
-    use std::{future::Future, time::Duration};
-    use tower_test::mock;
+    use std::collections::BTreeMap;
+    use std::sync::LazyLock;
+    use std::time::Duration;
+    use tower::Layer;
+    use tracing::span;
 
-    const API_RESOURCES: LazyCell<Value> = LazyCell::new(|| {
+    static API_RESOURCES: LazyLock<Value> = LazyLock::new(|| {
         json!({
             "kind":"APIResourceList",
             "groupVersion":"v1",
@@ -247,7 +831,7 @@ mod tests {
         })
     });
 
-    const EMPTY_API_RESOURCES: LazyCell<Value> = LazyCell::new(|| {
+    static EMPTY_API_RESOURCES: LazyLock<Value> = LazyLock::new(|| {
         json!({
             "kind":"APIResourceList",
             "groupVersion":"v1",
@@ -255,7 +839,7 @@ mod tests {
         })
     });
 
-    const POD: LazyCell<Value> = LazyCell::new(|| {
+    static POD: LazyLock<Value> = LazyLock::new(|| {
         json!({
             "apiVersion": "v1",
             "kind": "Pod",
@@ -268,7 +852,7 @@ mod tests {
         })
     });
 
-    const POD_NOT_FOUND_ERROR: LazyCell<Value> = LazyCell::new(|| {
+    static POD_NOT_FOUND_ERROR: LazyLock<Value> = LazyLock::new(|| {
         json!({
             "kind": "Status",
             "apiVersion": "v1",
@@ -284,7 +868,7 @@ mod tests {
         })
     });
 
-    const POD_DELETED_RESPONSE: LazyCell<Value> = LazyCell::new(|| {
+    static POD_DELETED_RESPONSE: LazyLock<Value> = LazyLock::new(|| {
         json!({
             "kind": "Status",
             "apiVersion": "v1",
@@ -298,7 +882,7 @@ mod tests {
         })
     });
 
-    const SVC: LazyCell<Value> = LazyCell::new(|| {
+    static SVC: LazyLock<Value> = LazyLock::new(|| {
         json!({
             "apiVersion": "v1",
             "kind": "Service",
@@ -309,7 +893,7 @@ mod tests {
         })
     });
 
-    const INTERNAL_ERROR: LazyCell<Value> = LazyCell::new(|| {
+    static INTERNAL_ERROR: LazyLock<Value> = LazyLock::new(|| {
         json!({
             "kind": "Status",
             "apiVersion": "v1",
@@ -371,13 +955,6 @@ mod tests {
         }
     }
 
-    fn unwrap_arc_mutex<T: std::fmt::Debug>(v: Arc<Mutex<T>>) -> T {
-        Arc::try_unwrap(v)
-            .expect("Arc should have only one reference")
-            .into_inner()
-            .unwrap()
-    }
-
     #[test_log::test(tokio::test)]
     #[test_log(default_log_filter = "deka=trace")]
     async fn apply_1_object() {
@@ -409,6 +986,7 @@ mod tests {
                 "test_manager",
                 Some("test_ns"),
                 &b,
+                Default::default(),
             )
             .await
             .unwrap();
@@ -461,6 +1039,7 @@ mod tests {
                 "test_manager",
                 Some("test_ns"),
                 &b,
+                Default::default(),
             )
             .await
             .unwrap();
@@ -513,6 +1092,7 @@ mod tests {
                 "test_manager",
                 Some("test_ns"),
                 &b,
+                Default::default(),
             )
             .await
             .unwrap();
@@ -562,6 +1142,7 @@ mod tests {
                 "test_manager",
                 None,
                 &b,
+                Default::default(),
             )
             .await
             .unwrap();
@@ -615,6 +1196,7 @@ mod tests {
                 "test_manager",
                 Some("test_ns"),
                 &b,
+                Default::default(),
             )
             .await
             .unwrap();
@@ -657,6 +1239,7 @@ mod tests {
                 "test_manager",
                 None,
                 &b,
+                Default::default(),
             )
             .await
             .unwrap();
@@ -711,6 +1294,7 @@ mod tests {
                 "test_manager",
                 Some("test_ns"),
                 &b,
+                Default::default(),
             )
             .await
             .unwrap();
@@ -744,6 +1328,7 @@ mod tests {
                 "test_manager",
                 None,
                 &b,
+                Default::default(),
             )
             .await
             .unwrap_err();
@@ -812,6 +1397,7 @@ mod tests {
                 "test_manager",
                 Some("test_ns"),
                 &b,
+                &ApplyParams::default(),
             )
             .await
             .unwrap();
@@ -836,9 +1422,16 @@ mod tests {
         let b = MockBackoff::new(LimitAndCount::default());
 
         with_mock_service(vec![], |s| async {
-            apply_objects(vec![], &Client::new(s, "default"), "test_manager", None, &b)
-                .await
-                .unwrap();
+            apply_objects(
+                vec![],
+                &Client::new(s, "default"),
+                "test_manager",
+                None,
+                &b,
+                &ApplyParams::default(),
+            )
+            .await
+            .unwrap();
         })
         .await;
 
@@ -896,6 +1489,7 @@ mod tests {
                 "test_manager",
                 Some("test_ns"),
                 &b,
+                Default::default(),
             )
             .await
             .unwrap();
@@ -965,6 +1559,7 @@ mod tests {
                 "test_manager",
                 Some("test_ns"),
                 &b,
+                Default::default(),
             )
             .await
             .unwrap();
@@ -985,134 +1580,944 @@ mod tests {
 
     #[test_log::test(tokio::test)]
     #[test_log(default_log_filter = "deka=trace")]
-    async fn retry_limit_is_effective() {
-        let expectations = vec![(
-            Request::get("/api/v1").body(Body::empty()).unwrap(),
+    async fn events_are_sent() {
+        let patch = || {
+            Request::patch(ssa_uri("test_ns", "pods", "example", "test_manager"))
+                .header("accept", "application/json")
+                .header("content-type", "application/apply-patch+yaml")
+                .body(Body::from(serde_json::to_vec(&*POD).unwrap()))
+                .unwrap()
+        };
+        let mut expectations: Vec<Expectation> = vec![];
+        for response in [
             Response::builder()
-                .body(Body::from(
-                    serde_json::to_vec(&*EMPTY_API_RESOURCES).unwrap(),
-                ))
+                .status(StatusCode::INTERNAL_SERVER_ERROR)
+                .body(Body::from(serde_json::to_vec(&*INTERNAL_ERROR).unwrap()))
                 .unwrap(),
-        )];
+            Response::builder()
+                .body(Body::from(serde_json::to_vec(&*POD).unwrap()))
+                .unwrap(),
+        ] {
+            expectations.push((
+                Request::get("/api/v1").body(Body::empty()).unwrap(),
+                Response::builder()
+                    .body(Body::from(serde_json::to_vec(&*API_RESOURCES).unwrap()))
+                    .unwrap(),
+            ));
+            expectations.push((patch(), response));
+        }
 
-        let b = MockBackoff::new(LimitAndCount::default());
+        let (tx, mut rx) = tokio::sync::mpsc::unbounded_channel();
+        let params = ApplyParams {
+            events: Some(tx),
+            ..Default::default()
+        };
 
         with_mock_service(expectations, |s| async {
-            apply_object(
-                &serde_json::from_value((*POD).clone()).unwrap(),
+            apply_objects(
+                vec![serde_json::from_value((*POD).clone()).unwrap()],
                 &Client::new(s, "default"),
                 "test_manager",
-                None,
-                &b,
+                Some("test_ns"),
+                &LimitAndCount {
+                    retry_limit: Some(1),
+                    ..Default::default()
+                },
+                &params,
             )
             .await
-            .unwrap_err();
+            .unwrap();
         })
         .await;
+        drop(params);
 
-        assert_eq!(
-            unwrap_arc_mutex(b.reset_calls),
-            1,
-            "unexpected number of reset calls"
-        );
-        assert_eq!(
-            unwrap_arc_mutex(b.next_backoff_calls),
-            1,
-            "unexpected number of next_backoff calls"
-        );
+        let mut kinds = vec![];
+        while let Some(e) = rx.recv().await {
+            assert_eq!(e.index, 0);
+            kinds.push(e.kind);
+        }
+        assert!(matches!(
+            kinds.as_slice(),
+            [
+                report::EventKind::AttemptFailed {
+                    cause: FailureCause::Unavailable,
+                    ..
+                },
+                report::EventKind::Succeeded
+            ]
+        ));
     }
 
     #[test_log::test(tokio::test)]
     #[test_log(default_log_filter = "deka=trace")]
-    async fn can_use_external_backoff() {
-        let b = backoffcrate::ExponentialBackoff::default();
+    async fn keep_applied_objects() {
+        let mut persisted = (*POD).clone();
+        persisted["metadata"]["uid"] = json!("5e0c3bd2-8d5c-4f2c-9b1b-2b4bd1f7a9b4");
+        let expectations = vec![
+            (
+                Request::get("/api/v1").body(Body::empty()).unwrap(),
+                Response::builder()
+                    .body(Body::from(serde_json::to_vec(&*API_RESOURCES).unwrap()))
+                    .unwrap(),
+            ),
+            (
+                Request::patch(ssa_uri("test_ns", "pods", "example", "test_manager"))
+                    .header("accept", "application/json")
+                    .header("content-type", "application/apply-patch+yaml")
+                    .body(Body::from(serde_json::to_vec(&*POD).unwrap()))
+                    .unwrap(),
+                Response::builder()
+                    .body(Body::from(serde_json::to_vec(&persisted).unwrap()))
+                    .unwrap(),
+            ),
+        ];
 
-        with_mock_service(vec![], |s| async {
-            apply_objects(vec![], &Client::new(s, "default"), "test_manager", None, &b)
-                .await
-                .unwrap();
+        with_mock_service(expectations, |s| async {
+            let report = apply_objects(
+                vec![serde_json::from_value((*POD).clone()).unwrap()],
+                &Client::new(s, "default"),
+                "test_manager",
+                Some("test_ns"),
+                &LimitAndCount::default(),
+                &ApplyParams {
+                    keep_applied: true,
+                    ..Default::default()
+                },
+            )
+            .await
+            .unwrap();
+            let applied = report.objects[0].applied.as_ref().unwrap();
+            assert_eq!(
+                applied.metadata.uid.as_deref(),
+                Some("5e0c3bd2-8d5c-4f2c-9b1b-2b4bd1f7a9b4")
+            );
         })
         .await;
     }
 
-    /// Asynchronously runs a mock "server" (the backend counterpart of a mock
-    /// [`tower::Service`]), that handles incoming requests and responds based
-    /// on predefined expectations.
-    ///
-    /// # Matching Requests
-    /// - Matches requests based on method, URI, headers, and HTTP version.
-    /// - Requests differing only in body are matched sequentially.
-    /// - Each expectation is consumed once it is matched. If the same request
-    ///   is expected multiple times, there should be multiple corresponding
-    ///   expectations.
-    ///
-    /// # Panics
-    /// - Panics upon receiving a request that doesn't match any remaining
-    ///   expectation.
-    /// - Panics when the body of a request doesn't match its expectation.
-    ///
-    /// # Note
-    /// This function panics in case of an unexpected request but does not
-    /// return, even when the last expectation is consumed. It is the caller's
-    /// responsibility to cancel the returned future when it is no longer
-    /// useful, i.e., when the client is done. This way, [`mock_server`] can be
-    /// safely used with [`tokio::select!`] without returning before the client
-    /// on successful applies.
-    async fn mock_server(
-        mut handle: mock::Handle<Request<Body>, Response<Body>>,
-        expectations: Arc<Mutex<Vec<(Request<Body>, Response<Body>)>>>,
-    ) {
-        loop {
-            let (request, send) = handle.next_request().await.expect("service not called");
-            let (expected_request, response) = {
-                let mut _expectations = expectations.lock().unwrap();
-                let matched_req_index = _expectations
-                    .iter()
-                    .position(|e| {
-                        e.0.method() == request.method()
-                            && e.0.uri() == request.uri()
-                            && e.0.headers() == request.headers()
-                            && e.0.version() == request.version()
-                    })
-                    .unwrap_or_else(|| panic!("unexpected request: {:#?}", request));
-                _expectations.remove(matched_req_index)
-            };
-            assert_eq!(
-                request.into_body().collect_bytes().await.unwrap(),
-                expected_request.into_body().collect_bytes().await.unwrap(),
-                "body does not match"
-            );
-            send.send_response(response);
-        }
-    }
+    #[test_log::test(tokio::test)]
+    #[test_log(default_log_filter = "deka=trace")]
+    async fn identify_cluster_scoped_objects_without_namespace() {
+        let namespace = json!({
+            "apiVersion": "v1",
+            "kind": "Namespace",
+            "metadata": { "name": "example" },
+        });
+        let resources = json!({
+            "kind": "APIResourceList",
+            "groupVersion": "v1",
+            "resources": [{
+                "name": "namespaces",
+                "singularName": "namespace",
+                "namespaced": false,
+                "kind": "Namespace",
+                "verbs": ["get", "patch"],
+            }],
+        });
+        let expectations = vec![
+            (
+                Request::get("/api/v1").body(Body::empty()).unwrap(),
+                Response::builder()
+                    .body(Body::from(serde_json::to_vec(&resources).unwrap()))
+                    .unwrap(),
+            ),
+            (
+                Request::patch("/api/v1/namespaces/example?&force=true&fieldManager=test_manager")
+                    .header("accept", "application/json")
+                    .header("content-type", "application/apply-patch+yaml")
+                    .body(Body::from(serde_json::to_vec(&namespace).unwrap()))
+                    .unwrap(),
+                Response::builder()
+                    .body(Body::from(serde_json::to_vec(&namespace).unwrap()))
+                    .unwrap(),
+            ),
+        ];
 
-    /// Creates a mock [`tower::Service`] that responds to requests with the
-    /// given expactations.
-    ///
-    /// Waits on `f` and ensure that all expactations have been consumed. See
-    /// [`mock_server`] for more information on how requests are matched.
-    ///
-    /// # Panics
-    /// - Panics if the service receives an unexpected request.
-    /// - Panics if `f` resolves while there are remaining expactations.
-    async fn with_mock_service<F, Fut>(expectations: Vec<(Request<Body>, Response<Body>)>, f: F)
-    where
-        F: FnOnce(mock::Mock<Request<Body>, Response<Body>>) -> Fut,
-        Fut: Future<Output = ()>,
-    {
-        let expectations = Arc::new(Mutex::new(expectations));
-        let (service, handle) = mock::pair::<Request<Body>, Response<Body>>();
-        tokio::select! {
-            _ = mock_server(handle, Arc::clone(&expectations)) => {}
-            _ = f(service) => {
-                let remaining_expectations = unwrap_arc_mutex(expectations);
-                assert!(
-                    remaining_expectations.is_empty(),
-                    "unmet expectation(s): {:#?}",
-                    remaining_expectations
-                );
-            }
-        };
+        with_mock_service(expectations, |s| async {
+            let report = apply_objects(
+                vec![serde_json::from_value(namespace.clone()).unwrap()],
+                &Client::new(s, "default"),
+                "test_manager",
+                None,
+                &LimitAndCount::default(),
+                &ApplyParams::default(),
+            )
+            .await
+            .unwrap();
+            let o = &report.objects[0];
+            assert_eq!(o.identity.as_ref().unwrap().namespace, None);
+            assert_eq!(o.label, "namespace/example");
+        })
+        .await;
+    }
+
+    /// Expectations for a Pod throttled once for 5s, then applied.
+    fn throttled_once_expectations() -> Vec<(Request<Body>, Response<Body>)> {
+        let api_resources = || {
+            (
+                Request::get("/api/v1").body(Body::empty()).unwrap(),
+                Response::builder()
+                    .body(Body::from(serde_json::to_vec(&*API_RESOURCES).unwrap()))
+                    .unwrap(),
+            )
+        };
+        let patch = || {
+            Request::patch(ssa_uri("test_ns", "pods", "example", "test_manager"))
+                .header("accept", "application/json")
+                .header("content-type", "application/apply-patch+yaml")
+                .body(Body::from(serde_json::to_vec(&*POD).unwrap()))
+                .unwrap()
+        };
+        let throttled = json!({
+            "kind": "Status",
+            "apiVersion": "v1",
+            "metadata": {},
+            "status": "Failure",
+            "message": "Too many requests, please try again later.",
+            "reason": "TooManyRequests",
+            "details": { "retryAfterSeconds": 5 },
+            "code": 429
+        });
+        vec![
+            api_resources(),
+            (
+                patch(),
+                Response::builder()
+                    .status(StatusCode::TOO_MANY_REQUESTS)
+                    .body(Body::from(serde_json::to_vec(&throttled).unwrap()))
+                    .unwrap(),
+            ),
+            api_resources(),
+            (
+                patch(),
+                Response::builder()
+                    .body(Body::from(serde_json::to_vec(&*POD).unwrap()))
+                    .unwrap(),
+            ),
+        ]
+    }
+
+    #[test_log::test(tokio::test(start_paused = true))]
+    #[test_log(default_log_filter = "deka=trace")]
+    async fn retry_throttled_once_allowed() {
+        let expectations = throttled_once_expectations();
+        with_mock_service(expectations, |s| async {
+            let throttling = throttling::RetryAfterLayer::new();
+            let start = tokio::time::Instant::now();
+            apply_object(
+                &serde_json::from_value((*POD).clone()).unwrap(),
+                &Client::new(throttling.layer(s), "default"),
+                "test_manager",
+                Some("test_ns"),
+                &LimitAndCount {
+                    interval: Duration::from_secs(30),
+                    retry_limit: Some(1),
+                    ..Default::default()
+                },
+                ObjectContext {
+                    throttling: Some(&throttling),
+                    ..Default::default()
+                },
+            )
+            .await
+            .unwrap();
+            assert_eq!(start.elapsed(), Duration::from_secs(5));
+        })
+        .await;
+    }
+
+    /// Waits twice as long as the API server asks when throttled.
+    #[derive(Clone)]
+    struct DoubleRetryAfter;
+
+    impl Backoff for DoubleRetryAfter {
+        fn next_backoff(&mut self) -> Option<Duration> {
+            Some(Duration::from_secs(30))
+        }
+
+        fn next_backoff_throttled(
+            &mut self,
+            _attempt: usize,
+            retry_after: Duration,
+        ) -> Option<Duration> {
+            Some(retry_after * 2)
+        }
+    }
+
+    #[test_log::test(tokio::test(start_paused = true))]
+    #[test_log(default_log_filter = "deka=trace")]
+    async fn backoff_decides_throttled_delay() {
+        let expectations = throttled_once_expectations();
+        with_mock_service(expectations, |s| async {
+            let throttling = throttling::RetryAfterLayer::new();
+            let start = tokio::time::Instant::now();
+            apply_object(
+                &serde_json::from_value((*POD).clone()).unwrap(),
+                &Client::new(throttling.layer(s), "default"),
+                "test_manager",
+                Some("test_ns"),
+                &DoubleRetryAfter,
+                ObjectContext {
+                    throttling: Some(&throttling),
+                    ..Default::default()
+                },
+            )
+            .await
+            .unwrap();
+            assert_eq!(start.elapsed(), Duration::from_secs(10));
+        })
+        .await;
+    }
+
+    #[test_log::test(tokio::test)]
+    #[test_log(default_log_filter = "deka=trace")]
+    async fn stamp_run_id() {
+        let mut pod = (*POD).clone();
+        pod["metadata"]["annotations"][ANNOTATION_RUN_ID] = json!("run-1");
+        let expectations = vec![
+            (
+                Request::get("/api/v1").body(Body::empty()).unwrap(),
+                Response::builder()
+                    .body(Body::from(serde_json::to_vec(&*API_RESOURCES).unwrap()))
+                    .unwrap(),
+            ),
+            (
+                Request::patch(ssa_uri("test_ns", "pods", "example", "test_manager"))
+                    .header("accept", "application/json")
+                    .header("content-type", "application/apply-patch+yaml")
+                    .body(Body::from(serde_json::to_vec(&pod).unwrap()))
+                    .unwrap(),
+                Response::builder()
+                    .body(Body::from(serde_json::to_vec(&pod).unwrap()))
+                    .unwrap(),
+            ),
+        ];
+
+        with_mock_service(expectations, |s| async {
+            apply_object(
+                &serde_json::from_value((*POD).clone()).unwrap(),
+                &Client::new(s, "default"),
+                "test_manager",
+                Some("test_ns"),
+                &LimitAndCount::default(),
+                ObjectContext {
+                    run_id: Some("run-1"),
+                    ..Default::default()
+                },
+            )
+            .await
+            .unwrap();
+        })
+        .await;
+    }
+
+    /// Records the fields of the `attempt` spans once closed.
+    #[derive(Clone, Default)]
+    struct AttemptRecorder {
+        attempts: Arc<Mutex<Vec<BTreeMap<String, String>>>>,
+    }
+
+    struct Fields(BTreeMap<String, String>);
+
+    impl field::Visit for Fields {
+        fn record_debug(&mut self, field: &field::Field, value: &dyn std::fmt::Debug) {
+            self.0
+                .insert(field.name().to_string(), format!("{:?}", value));
+        }
+
+        fn record_str(&mut self, field: &field::Field, value: &str) {
+            self.0.insert(field.name().to_string(), value.to_string());
+        }
+    }
+
+    impl<S> tracing_subscriber::Layer<S> for AttemptRecorder
+    where
+        S: tracing::Subscriber + for<'a> tracing_subscriber::registry::LookupSpan<'a>,
+    {
+        fn on_new_span(
+            &self,
+            attrs: &span::Attributes<'_>,
+            id: &span::Id,
+            ctx: tracing_subscriber::layer::Context<'_, S>,
+        ) {
+            if attrs.metadata().name() == "attempt" {
+                let mut fields = Fields(BTreeMap::new());
+                attrs.record(&mut fields);
+                ctx.span(id).unwrap().extensions_mut().insert(fields);
+            }
+        }
+
+        fn on_record(
+            &self,
+            id: &span::Id,
+            values: &span::Record<'_>,
+            ctx: tracing_subscriber::layer::Context<'_, S>,
+        ) {
+            if let Some(fields) = ctx.span(id).unwrap().extensions_mut().get_mut::<Fields>() {
+                values.record(fields);
+            }
+        }
+
+        fn on_close(&self, id: span::Id, ctx: tracing_subscriber::layer::Context<'_, S>) {
+            if let Some(fields) = ctx.span(&id).unwrap().extensions_mut().remove::<Fields>() {
+                self.attempts.lock().unwrap().push(fields.0);
+            }
+        }
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn record_attempt_span_fields() {
+        use tracing_subscriber::layer::SubscriberExt;
+
+        let recorder = AttemptRecorder::default();
+        let _guard =
+            tracing::subscriber::set_default(tracing_subscriber::registry().with(recorder.clone()));
+        let b = LimitAndCount {
+            interval: Duration::from_secs(1),
+            retry_limit: Some(1),
+            ..Default::default()
+        };
+
+        with_mock_service(empty_api_resources_expectations(2), |s| async {
+            apply_object(
+                &serde_json::from_value((*POD).clone()).unwrap(),
+                &Client::new(s, "default"),
+                "test_manager",
+                None,
+                &b,
+                ObjectContext::default(),
+            )
+            .await
+            .unwrap_err();
+        })
+        .await;
+
+        let fields = |pairs: &[(&str, &str)]| {
+            pairs
+                .iter()
+                .map(|(k, v)| (k.to_string(), v.to_string()))
+                .collect::<BTreeMap<_, _>>()
+        };
+        assert_eq!(
+            *recorder.attempts.lock().unwrap(),
+            [
+                fields(&[
+                    ("attempt", "1"),
+                    ("elapsed_ms", "0"),
+                    ("cause", "missing-kind"),
+                    ("delay_ms", "1000"),
+                ]),
+                fields(&[
+                    ("attempt", "2"),
+                    ("elapsed_ms", "1000"),
+                    ("cause", "missing-kind"),
+                ]),
+            ]
+        );
+    }
+
+    #[test_log::test(tokio::test)]
+    #[test_log(default_log_filter = "deka=trace")]
+    async fn retry_limit_is_effective() {
+        let expectations = vec![(
+            Request::get("/api/v1").body(Body::empty()).unwrap(),
+            Response::builder()
+                .body(Body::from(
+                    serde_json::to_vec(&*EMPTY_API_RESOURCES).unwrap(),
+                ))
+                .unwrap(),
+        )];
+
+        let b = MockBackoff::new(LimitAndCount::default());
+
+        with_mock_service(expectations, |s| async {
+            apply_object(
+                &serde_json::from_value((*POD).clone()).unwrap(),
+                &Client::new(s, "default"),
+                "test_manager",
+                None,
+                &b,
+                Default::default(),
+            )
+            .await
+            .unwrap_err();
+        })
+        .await;
+
+        assert_eq!(
+            unwrap_arc_mutex(b.reset_calls),
+            1,
+            "unexpected number of reset calls"
+        );
+        assert_eq!(
+            unwrap_arc_mutex(b.next_backoff_calls),
+            1,
+            "unexpected number of next_backoff calls"
+        );
+    }
+
+    static INVALID_ERROR: LazyLock<Value> = LazyLock::new(|| {
+        json!({
+            "kind": "Status",
+            "apiVersion": "v1",
+            "metadata": {},
+            "status": "Failure",
+            "message": "Pod \"example\" is invalid: spec.containers[0].image: Required value",
+            "reason": "Invalid",
+            "code": 422
+        })
+    });
+
+    /// Retries right away, except after rejections, recording what each
+    /// decision was based on.
+    #[derive(Clone, Default)]
+    struct GiveUpOnRejection {
+        calls: Arc<Mutex<Vec<(FailureCause, usize)>>>,
+    }
+
+    impl Backoff for GiveUpOnRejection {
+        fn next_backoff(&mut self) -> Option<Duration> {
+            unreachable!("next_backoff_after should be called instead")
+        }
+
+        fn next_backoff_after(&mut self, cause: FailureCause, attempt: usize) -> Option<Duration> {
+            self.calls.lock().unwrap().push((cause, attempt));
+            (cause != FailureCause::Rejected).then_some(Duration::ZERO)
+        }
+    }
+
+    #[test_log::test(tokio::test)]
+    #[test_log(default_log_filter = "deka=trace")]
+    async fn backoff_is_given_failure_cause() {
+        let patch = || {
+            Request::patch(ssa_uri("test_ns", "pods", "example", "test_manager"))
+                .header("accept", "application/json")
+                .header("content-type", "application/apply-patch+yaml")
+                .body(Body::from(serde_json::to_vec(&*POD).unwrap()))
+                .unwrap()
+        };
+        let mut expectations = empty_api_resources_expectations(1);
+        expectations.extend([
+            (
+                Request::get("/api/v1").body(Body::empty()).unwrap(),
+                Response::builder()
+                    .body(Body::from(serde_json::to_vec(&*API_RESOURCES).unwrap()))
+                    .unwrap(),
+            ),
+            (
+                patch(),
+                Response::builder()
+                    .status(StatusCode::INTERNAL_SERVER_ERROR)
+                    .body(Body::from(serde_json::to_vec(&*INTERNAL_ERROR).unwrap()))
+                    .unwrap(),
+            ),
+            (
+                Request::get("/api/v1").body(Body::empty()).unwrap(),
+                Response::builder()
+                    .body(Body::from(serde_json::to_vec(&*API_RESOURCES).unwrap()))
+                    .unwrap(),
+            ),
+            (
+                patch(),
+                Response::builder()
+                    .status(StatusCode::UNPROCESSABLE_ENTITY)
+                    .body(Body::from(serde_json::to_vec(&*INVALID_ERROR).unwrap()))
+                    .unwrap(),
+            ),
+        ]);
+
+        let b = GiveUpOnRejection::default();
+
+        with_mock_service(expectations, |s| async {
+            let err = apply_object(
+                &serde_json::from_value((*POD).clone()).unwrap(),
+                &Client::new(s, "default"),
+                "test_manager",
+                Some("test_ns"),
+                &b,
+                Default::default(),
+            )
+            .await
+            .unwrap_err();
+            assert!(matches!(err, ApplyError::Kube(KubeError::Api(r)) if r.code == 422));
+        })
+        .await;
+
+        assert_eq!(
+            unwrap_arc_mutex(b.calls),
+            vec![
+                (FailureCause::MissingKind, 1),
+                (FailureCause::Unavailable, 2),
+                (FailureCause::Rejected, 3),
+            ]
+        );
+    }
+
+    fn empty_api_resources_expectations(count: usize) -> Vec<Expectation> {
+        (0..count)
+            .map(|_| {
+                (
+                    Request::get("/api/v1").body(Body::empty()).unwrap(),
+                    Response::builder()
+                        .body(Body::from(
+                            serde_json::to_vec(&*EMPTY_API_RESOURCES).unwrap(),
+                        ))
+                        .unwrap(),
+                )
+            })
+            .collect()
+    }
+
+    #[test_log::test(tokio::test(start_paused = true))]
+    #[test_log(default_log_filter = "deka=trace")]
+    async fn object_timeout_annotation_is_effective() {
+        let mut pod = (*POD).clone();
+        pod["metadata"]["annotations"][ANNOTATION_TIMEOUT] = json!("1s");
+
+        let b = LimitAndCount {
+            interval: Duration::from_millis(400),
+            retry_limit: Some(10),
+            ..Default::default()
+        };
+
+        with_mock_service(empty_api_resources_expectations(3), |s| async {
+            let err = apply_object(
+                &serde_json::from_value(pod).unwrap(),
+                &Client::new(s, "default"),
+                "test_manager",
+                None,
+                &b,
+                ObjectContext {
+                    timeout: Some(Duration::from_secs(60)),
+                    ..Default::default()
+                },
+            )
+            .await
+            .unwrap_err();
+            assert!(matches!(err, ApplyError::TimedOut { attempts: 3, .. }));
+        })
+        .await;
+    }
+
+    #[test_log::test(tokio::test(start_paused = true))]
+    #[test_log(default_log_filter = "deka=trace")]
+    async fn zero_object_timeout_annotation_means_no_limit() {
+        let mut pod = (*POD).clone();
+        pod["metadata"]["annotations"][ANNOTATION_TIMEOUT] = json!("0");
+
+        let b = LimitAndCount {
+            interval: Duration::from_millis(400),
+            retry_limit: Some(2),
+            ..Default::default()
+        };
+
+        with_mock_service(empty_api_resources_expectations(3), |s| async {
+            let err = apply_object(
+                &serde_json::from_value(pod).unwrap(),
+                &Client::new(s, "default"),
+                "test_manager",
+                None,
+                &b,
+                ObjectContext {
+                    timeout: Some(Duration::from_millis(500)),
+                    ..Default::default()
+                },
+            )
+            .await
+            .unwrap_err();
+            assert!(matches!(err, ApplyError::Kube(_)));
+        })
+        .await;
+    }
+
+    #[test_log::test(tokio::test(start_paused = true))]
+    #[test_log(default_log_filter = "deka=trace")]
+    async fn kind_override_is_effective() {
+        let overrides = [
+            overrides::KindOverride {
+                kind: Some("Service".to_string()),
+                timeout: Some(Duration::ZERO),
+                ..Default::default()
+            },
+            overrides::KindOverride {
+                group: Some("".to_string()),
+                kind: Some("Pod".to_string()),
+                retry: Some(backoff::RetryPolicyPatch {
+                    max_attempts: Some(2),
+                    ..Default::default()
+                }),
+                ..Default::default()
+            },
+        ];
+
+        let b = MockBackoff::new(LimitAndCount {
+            retry_limit: Some(10),
+            ..Default::default()
+        });
+
+        with_mock_service(empty_api_resources_expectations(2), |s| async {
+            let err = apply_object(
+                &serde_json::from_value((*POD).clone()).unwrap(),
+                &Client::new(s, "default"),
+                "test_manager",
+                None,
+                &b,
+                ObjectContext {
+                    overrides: &overrides,
+                    ..Default::default()
+                },
+            )
+            .await
+            .unwrap_err();
+            assert!(matches!(err, ApplyError::Kube(_)));
+        })
+        .await;
+
+        assert_eq!(
+            unwrap_arc_mutex(b.next_backoff_calls),
+            0,
+            "unexpected number of next_backoff calls"
+        );
+    }
+
+    #[test_log::test(tokio::test(start_paused = true))]
+    #[test_log(default_log_filter = "deka=trace")]
+    async fn partial_kind_override_inherits_policy() {
+        let overrides = [overrides::KindOverride {
+            kind: Some("Pod".to_string()),
+            retry: Some(backoff::RetryPolicyPatch {
+                max_attempts: Some(2),
+                ..Default::default()
+            }),
+            ..Default::default()
+        }];
+        let retry = backoff::RetryPolicy {
+            strategy: backoff::Strategy::Constant,
+            initial: Duration::from_secs(10),
+            jitter: 0.0,
+            ..Default::default()
+        };
+
+        with_mock_service(empty_api_resources_expectations(2), |s| async {
+            let start = tokio::time::Instant::now();
+            let err = apply_object(
+                &serde_json::from_value((*POD).clone()).unwrap(),
+                &Client::new(s, "default"),
+                "test_manager",
+                None,
+                &LimitAndCount::default(),
+                ObjectContext {
+                    overrides: &overrides,
+                    retry: Some(&retry),
+                    ..Default::default()
+                },
+            )
+            .await
+            .unwrap_err();
+            assert!(matches!(err, ApplyError::Kube(_)));
+            assert_eq!(start.elapsed(), Duration::from_secs(10));
+        })
+        .await;
+    }
+
+    #[test_log::test(tokio::test(start_paused = true))]
+    #[test_log(default_log_filter = "deka=trace")]
+    async fn deadline_is_effective() {
+        let b = LimitAndCount {
+            interval: Duration::from_millis(400),
+            retry_limit: Some(10),
+            ..Default::default()
+        };
+
+        with_mock_service(empty_api_resources_expectations(3), |s| async {
+            let errors = apply_objects(
+                vec![serde_json::from_value((*POD).clone()).unwrap()],
+                &Client::new(s, "default"),
+                "test_manager",
+                None,
+                &b,
+                &ApplyParams {
+                    deadline: Some(Duration::from_secs(1)),
+                    ..Default::default()
+                },
+            )
+            .await
+            .unwrap_err();
+            assert!(matches!(
+                errors.report().errors().collect::<Vec<_>>().as_slice(),
+                [ApplyError::DeadlineExceeded { attempts: 3, .. }]
+            ));
+        })
+        .await;
+    }
+
+    #[test_log::test(tokio::test(start_paused = true))]
+    #[test_log(default_log_filter = "deka=trace")]
+    async fn cancellation_stops_retries() {
+        let b = LimitAndCount {
+            interval: Duration::from_secs(10),
+            retry_limit: Some(10),
+            ..Default::default()
+        };
+        let params = ApplyParams {
+            cancel: CancellationToken::new(),
+            ..Default::default()
+        };
+
+        with_mock_service(empty_api_resources_expectations(1), |s| async {
+            let client = &Client::new(s, "default");
+            let (result, _) = tokio::join!(
+                apply_objects(
+                    vec![serde_json::from_value((*POD).clone()).unwrap()],
+                    client,
+                    "test_manager",
+                    None,
+                    &b,
+                    &params,
+                ),
+                async {
+                    tokio::time::sleep(Duration::from_secs(1)).await;
+                    params.cancel.cancel();
+                }
+            );
+            let report = result.unwrap_err().into_report();
+            assert!(matches!(
+                report.objects.as_slice(),
+                [ObjectReport {
+                    attempts: 1,
+                    error: Some(ApplyError::Cancelled { attempts: 1, .. }),
+                    ..
+                }]
+            ));
+        })
+        .await;
+    }
+
+    #[test_log::test(tokio::test(start_paused = true))]
+    #[test_log(default_log_filter = "deka=trace")]
+    async fn breaker_pauses_attempts_until_ready() {
+        let patch = || {
+            Request::patch(ssa_uri("test_ns", "pods", "example", "test_manager"))
+                .header("accept", "application/json")
+                .header("content-type", "application/apply-patch+yaml")
+                .body(Body::from(serde_json::to_vec(&*POD).unwrap()))
+                .unwrap()
+        };
+        let api_resources = || {
+            (
+                Request::get("/api/v1").body(Body::empty()).unwrap(),
+                Response::builder()
+                    .body(Body::from(serde_json::to_vec(&*API_RESOURCES).unwrap()))
+                    .unwrap(),
+            )
+        };
+        let expectations = vec![
+            api_resources(),
+            (
+                patch(),
+                Response::builder()
+                    .status(StatusCode::INTERNAL_SERVER_ERROR)
+                    .body(Body::from(serde_json::to_vec(&*INTERNAL_ERROR).unwrap()))
+                    .unwrap(),
+            ),
+            (
+                Request::get("/readyz").body(Body::empty()).unwrap(),
+                Response::builder()
+                    .body(Body::from(b"ok".to_vec()))
+                    .unwrap(),
+            ),
+            api_resources(),
+            (
+                patch(),
+                Response::builder()
+                    .body(Body::from(serde_json::to_vec(&*POD).unwrap()))
+                    .unwrap(),
+            ),
+        ];
+
+        let b = LimitAndCount {
+            retry_limit: Some(10),
+            ..Default::default()
+        };
+
+        with_mock_service(expectations, |s| async {
+            let start = tokio::time::Instant::now();
+            let report = apply_objects(
+                vec![serde_json::from_value((*POD).clone()).unwrap()],
+                &Client::new(s, "default"),
+                "test_manager",
+                Some("test_ns"),
+                &b,
+                &ApplyParams {
+                    breaker_threshold: Some(1),
+                    ..Default::default()
+                },
+            )
+            .await
+            .unwrap();
+            assert_eq!(report.objects[0].attempts, 2);
+            assert_eq!(start.elapsed(), breaker::PROBE_INTERVAL);
+        })
+        .await;
+    }
+
+    #[test]
+    fn classify_api_errors() {
+        let api_error = |code| {
+            KubeError::Api(kube::core::ErrorResponse {
+                status: "Failure".to_string(),
+                message: String::new(),
+                reason: String::new(),
+                code,
+            })
+        };
+        assert_eq!(
+            FailureCause::of(&api_error(401)),
+            FailureCause::Unauthorized
+        );
+        assert_eq!(
+            FailureCause::of(&api_error(403)),
+            FailureCause::Unauthorized
+        );
+        assert_eq!(FailureCause::of(&api_error(422)), FailureCause::Rejected);
+        assert_eq!(FailureCause::of(&api_error(429)), FailureCause::Throttled);
+        assert_eq!(FailureCause::of(&api_error(503)), FailureCause::Unavailable);
+    }
+
+    #[test]
+    fn parse_durations() {
+        assert_eq!(parse_duration("90").unwrap(), Duration::from_secs(90));
+        assert_eq!(parse_duration("1.5").unwrap(), Duration::from_millis(1500));
+        assert_eq!(parse_duration("300ms").unwrap(), Duration::from_millis(300));
+        assert_eq!(parse_duration("1h30m").unwrap(), Duration::from_secs(5400));
+        assert_eq!(
+            parse_duration("2m0.5s").unwrap(),
+            Duration::from_millis(120_500)
+        );
+        assert!(parse_duration("").is_err());
+        assert!(parse_duration("5d").is_err());
+        assert!(parse_duration("m").is_err());
+        assert!(parse_duration("-1s").is_err());
+    }
+
+    #[test_log::test(tokio::test)]
+    #[test_log(default_log_filter = "deka=trace")]
+    async fn can_use_external_backoff() {
+        let b = ::backoff::ExponentialBackoff::default();
+
+        with_mock_service(vec![], |s| async {
+            apply_objects(
+                vec![],
+                &Client::new(s, "default"),
+                "test_manager",
+                None,
+                &b,
+                &ApplyParams::default(),
+            )
+            .await
+            .unwrap();
+        })
+        .await;
     }
 
     /// Encapsulates a long format string that causes code formatting issues
@@ -0,0 +1,207 @@
+//! Prometheus metrics of apply runs.
+
+use crate::{report::Report, Action};
+use futures::future::BoxFuture;
+use http::{Request, Response, StatusCode};
+use prometheus_client::{
+    encoding::EncodeLabelSet,
+    metrics::{
+        counter::Counter,
+        family::Family,
+        histogram::{exponential_buckets, Histogram},
+    },
+    registry::Registry,
+};
+use std::{
+    task::{Context, Poll},
+    time::Duration,
+};
+use tokio::time::Instant;
+use tower::{Layer, Service};
+
+#[derive(Clone, Debug, Hash, PartialEq, Eq, EncodeLabelSet)]
+struct ObjectLabels {
+    kind: String,
+    /// `applied`, `deleted` or `failed`.
+    outcome: &'static str,
+}
+
+#[derive(Clone, Debug, Hash, PartialEq, Eq, EncodeLabelSet)]
+struct RequestLabels {
+    /// HTTP method of the request, lowercased.
+    verb: String,
+}
+
+/// Metrics of [`apply_objects`](crate::apply_objects) runs, and of the requests
+/// made with a client built with [`Metrics::layer`].
+///
+/// Clones share the same metrics.
+#[derive(Clone, Debug)]
+pub struct Metrics {
+    objects: Family<ObjectLabels, Counter>,
+    attempts: Histogram,
+    convergence: Histogram,
+    requests: Family<RequestLabels, Histogram>,
+    throttled: Counter,
+}
+
+impl Metrics {
+    /// Creates metrics registered in `registry`, named with a `deka_` prefix.
+    pub fn new(registry: &mut Registry) -> Self {
+        let metrics = Self {
+            objects: Family::default(),
+            attempts: Histogram::new(exponential_buckets(1.0, 2.0, 8)),
+            convergence: Histogram::new(exponential_buckets(0.5, 2.0, 12)),
+            requests: Family::new_with_constructor(|| {
+                Histogram::new(exponential_buckets(0.005, 2.0, 12))
+            }),
+            throttled: Counter::default(),
+        };
+
+        let registry = registry.sub_registry_with_prefix("deka");
+        registry.register(
+            "objects",
+            "Objects applied, deleted or given up on, by kind",
+            metrics.objects.clone(),
+        );
+        registry.register(
+            "object_attempts",
+            "Attempts made at applying or deleting each object",
+            metrics.attempts.clone(),
+        );
+        registry.register(
+            "convergence_seconds",
+            "Time until all objects were applied or given up on",
+            metrics.convergence.clone(),
+        );
+        registry.register(
+            "request_duration_seconds",
+            "Latency of the requests made to the API server, by verb",
+            metrics.requests.clone(),
+        );
+        registry.register(
+            "throttled_requests",
+            "Requests the API server throttled",
+            metrics.throttled.clone(),
+        );
+        metrics
+    }
+
+    /// Returns a layer recording the requests of a client in these metrics.
+    pub fn layer(&self) -> MetricsLayer {
+        MetricsLayer {
+            metrics: self.clone(),
+        }
+    }
+
+    /// Records the outcome of an [`apply_objects`](crate::apply_objects) run
+    /// that took `elapsed`.
+    pub(crate) fn record(&self, report: &Report, elapsed: Duration) {
+        for o in &report.objects {
+            let outcome = match (&o.error, o.action) {
+                (Some(_), _) => "failed",
+                (None, Some(Action::Delete)) => "deleted",
+                (None, _) => "applied",
+            };
+            let kind = o.identity.as_ref().map(|i| i.kind.clone());
+            self.objects
+                .get_or_create(&ObjectLabels {
+                    kind: kind.unwrap_or_default(),
+                    outcome,
+                })
+                .inc();
+            self.attempts.observe(o.attempts as f64);
+        }
+        self.convergence.observe(elapsed.as_secs_f64());
+    }
+}
+
+/// Records the latency of requests, and the ones throttled.
+#[derive(Clone, Debug)]
+pub struct MetricsLayer {
+    metrics: Metrics,
+}
+
+impl<S> Layer<S> for MetricsLayer {
+    type Service = RequestMetrics<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        RequestMetrics {
+            inner,
+            metrics: self.metrics.clone(),
+        }
+    }
+}
+
+/// Service created by [`MetricsLayer`].
+#[derive(Clone, Debug)]
+pub struct RequestMetrics<S> {
+    inner: S,
+    metrics: Metrics,
+}
+
+impl<S, ReqBody, ResBody> Service<Request<ReqBody>> for RequestMetrics<S>
+where
+    S: Service<Request<ReqBody>, Response = Response<ResBody>>,
+    S::Future: Send + 'static,
+{
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = BoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, req: Request<ReqBody>) -> Self::Future {
+        let verb = req.method().as_str().to_lowercase();
+        let metrics = self.metrics.clone();
+        let started = Instant::now();
+        let resp = self.inner.call(req);
+        Box::pin(async move {
+            let resp = resp.await;
+            metrics
+                .requests
+                .get_or_create(&RequestLabels { verb })
+                .observe(started.elapsed().as_secs_f64());
+            if matches!(&resp, Ok(r) if r.status() == StatusCode::TOO_MANY_REQUESTS) {
+                metrics.throttled.inc();
+            }
+            resp
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use prometheus_client::encoding::text::encode;
+    use tower_test::mock;
+
+    #[tokio::test]
+    async fn count_throttled_requests() {
+        let mut registry = Registry::default();
+        let metrics = Metrics::new(&mut registry);
+        let (service, mut handle) = mock::pair::<Request<()>, Response<()>>();
+        let mut service = metrics.layer().layer(service);
+        handle.allow(1);
+
+        futures::future::poll_fn(|cx| service.poll_ready(cx))
+            .await
+            .unwrap();
+        let resp = service.call(Request::patch("/api/v1").body(()).unwrap());
+        let (_, send) = handle.next_request().await.unwrap();
+        send.send_response(
+            Response::builder()
+                .status(StatusCode::TOO_MANY_REQUESTS)
+                .body(())
+                .unwrap(),
+        );
+        resp.await.unwrap();
+
+        let mut text = String::new();
+        encode(&mut text, &registry).unwrap();
+        assert!(text.contains("deka_throttled_requests_total 1\n"));
+        assert!(text.contains("deka_request_duration_seconds_count{verb=\"patch\"} 1\n"));
+    }
+}
@@ -0,0 +1,100 @@
+//! Helpers to test code interacting with the Kubernetes API against a mock
+//! server.
+
+use http::{Request, Response};
+use kube::client::Body;
+use std::{
+    future::Future,
+    sync::{Arc, Mutex},
+};
+use tower_test::mock;
+
+pub(crate) fn unwrap_arc_mutex<T: std::fmt::Debug>(v: Arc<Mutex<T>>) -> T {
+    Arc::try_unwrap(v)
+        .expect("Arc should have only one reference")
+        .into_inner()
+        .unwrap()
+}
+
+/// A request expected by [`mock_server`] and the response to send back.
+pub(crate) type Expectation = (Request<Body>, Response<Body>);
+
+/// Asynchronously runs a mock "server" (the backend counterpart of a mock
+/// [`tower::Service`]), that handles incoming requests and responds based
+/// on predefined expectations.
+///
+/// # Matching Requests
+/// - Matches requests based on method, URI, headers, and HTTP version.
+/// - Requests differing only in body are matched sequentially.
+/// - Each expectation is consumed once it is matched. If the same request
+///   is expected multiple times, there should be multiple corresponding
+///   expectations.
+///
+/// # Panics
+/// - Panics upon receiving a request that doesn't match any remaining
+///   expectation.
+/// - Panics when the body of a request doesn't match its expectation.
+///
+/// # Note
+/// This function panics in case of an unexpected request but does not
+/// return, even when the last expectation is consumed. It is the caller's
+/// responsibility to cancel the returned future when it is no longer
+/// useful, i.e., when the client is done. This way, [`mock_server`] can be
+/// safely used with [`tokio::select!`] without returning before the client
+/// on successful applies.
+async fn mock_server(
+    mut handle: mock::Handle<Request<Body>, Response<Body>>,
+    expectations: Arc<Mutex<Vec<Expectation>>>,
+) {
+    loop {
+        let (request, send) = handle.next_request().await.expect("service not called");
+        let (expected_request, response) = {
+            let mut _expectations = expectations.lock().unwrap();
+            let matched_req_index = _expectations
+                .iter()
+                .position(|e| {
+                    e.0.method() == request.method()
+                        && e.0.uri() == request.uri()
+                        && e.0.headers() == request.headers()
+                        && e.0.version() == request.version()
+                })
+                .unwrap_or_else(|| panic!("unexpected request: {:#?}", request));
+            _expectations.remove(matched_req_index)
+        };
+        assert_eq!(
+            request.into_body().collect_bytes().await.unwrap(),
+            expected_request.into_body().collect_bytes().await.unwrap(),
+            "body does not match"
+        );
+        send.send_response(response);
+    }
+}
+
+/// Creates a mock [`tower::Service`] that responds to requests with the
+/// given expactations.
+///
+/// Waits on `f` and ensure that all expactations have been consumed. See
+/// [`mock_server`] for more information on how requests are matched.
+///
+/// # Panics
+/// - Panics if the service receives an unexpected request.
+/// - Panics if `f` resolves while there are remaining expactations.
+pub(crate) async fn with_mock_service<F, Fut>(expectations: Vec<Expectation>, f: F)
+where
+    F: FnOnce(mock::Mock<Request<Body>, Response<Body>>) -> Fut,
+    Fut: Future<Output = ()>,
+{
+    let expectations = Arc::new(Mutex::new(expectations));
+    let (service, handle) = mock::pair::<Request<Body>, Response<Body>>();
+    tokio::select! {
+        _ = mock_server(handle, Arc::clone(&expectations)) => {}
+        _ = f(service) => {
+            let remaining_expectations = unwrap_arc_mutex(expectations);
+            assert!(
+                remaining_expectations.is_empty(),
+                "unmet expectation(s): {:#?}",
+                remaining_expectations
+            );
+        }
+    };
+}
@@ -0,0 +1,118 @@
+//! Retry settings overridden for the objects of some kinds.
+
+use crate::{backoff::RetryPolicyPatch, deserialize_optional_duration};
+use kube::core::GroupVersionKind;
+use serde::Deserialize;
+use std::time::Duration;
+
+/// Retry settings applying to the objects matching a selector, e.g. as read
+/// from a configuration file:
+///
+/// ```yaml
+/// group: cert-manager.io
+/// kind: Certificate
+/// timeout: 10m
+/// retry:
+///   strategy: constant
+///   initial: 5s
+/// ```
+///
+/// Objects are selected by group, version and kind. Each of them matches
+/// anything when unset, and can contain `*` wildcards. The core group is the
+/// empty string. The `deka.ndrpnt.dev/timeout` annotation of an object still
+/// takes precedence over the timeout.
+#[derive(Clone, Debug, Default, PartialEq, Deserialize)]
+#[serde(rename_all = "camelCase", deny_unknown_fields)]
+pub struct KindOverride {
+    pub group: Option<String>,
+    pub version: Option<String>,
+    pub kind: Option<String>,
+    /// Give up on matching objects once this long has elapsed since their
+    /// first attempt.
+    #[serde(default, deserialize_with = "deserialize_optional_duration")]
+    pub timeout: Option<Duration>,
+    /// Retry matching objects following this policy rather than the default
+    /// backoff. Fields left unset are inherited from the default policy.
+    pub retry: Option<RetryPolicyPatch>,
+}
+
+impl KindOverride {
+    pub fn matches(&self, gvk: &GroupVersionKind) -> bool {
+        let matches = |pattern: &Option<String>, value: &str| {
+            pattern.as_deref().is_none_or(|p| glob_match(p, value))
+        };
+        matches(&self.group, &gvk.group)
+            && matches(&self.version, &gvk.version)
+            && matches(&self.kind, &gvk.kind)
+    }
+}
+
+/// Returns the first override matching `gvk`, if any.
+pub(crate) fn find<'a>(
+    overrides: &'a [KindOverride],
+    gvk: &GroupVersionKind,
+) -> Option<&'a KindOverride> {
+    overrides.iter().find(|o| o.matches(gvk))
+}
+
+/// Matches `value` against `pattern`, in which `*` matches any sequence of
+/// characters.
+pub(crate) fn glob_match(pattern: &str, value: &str) -> bool {
+    match pattern.split_once('*') {
+        None => pattern == value,
+        Some((prefix, rest)) => {
+            let Some(value) = value.strip_prefix(prefix) else {
+                return false;
+            };
+            (0..=value.len())
+                .filter(|i| value.is_char_boundary(*i))
+                .any(|i| glob_match(rest, &value[i..]))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn gvk(group: &str, version: &str, kind: &str) -> GroupVersionKind {
+        GroupVersionKind::gvk(group, version, kind)
+    }
+
+    #[test]
+    fn match_globs() {
+        assert!(glob_match("cert-manager.io", "cert-manager.io"));
+        assert!(glob_match("*.k8s.io", "networking.k8s.io"));
+        assert!(glob_match("v1*", "v1beta1"));
+        assert!(glob_match("*", ""));
+        assert!(!glob_match("*.k8s.io", "cert-manager.io"));
+        assert!(!glob_match("", "apps"));
+    }
+
+    #[test]
+    fn find_first_matching_override() {
+        let overrides: Vec<KindOverride> = serde_yaml::from_str(
+            "
+            - group: cert-manager.io
+              kind: Certificate
+              timeout: 10m
+            - group: ''
+              retry:
+                maxAttempts: 1
+            - kind: '*'
+              timeout: 1m
+            ",
+        )
+        .unwrap();
+
+        let certificate = find(&overrides, &gvk("cert-manager.io", "v1", "Certificate"));
+        assert_eq!(certificate.unwrap().timeout, Some(Duration::from_secs(600)));
+
+        let pod = find(&overrides, &gvk("", "v1", "Pod")).unwrap();
+        assert_eq!(pod.timeout, None);
+        assert_eq!(pod.retry.as_ref().unwrap().max_attempts, Some(1));
+
+        let deployment = find(&overrides, &gvk("apps", "v1", "Deployment"));
+        assert_eq!(deployment.unwrap().timeout, Some(Duration::from_secs(60)));
+    }
+}
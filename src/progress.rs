@@ -0,0 +1,253 @@
+//! Tracking of the progress made while applying a set of objects, used to
+//! detect when it stalls.
+
+use crate::{
+    analysis::ObjectIdentity,
+    report::{Event, EventKind, FailedAttempt},
+    ApplyError, FailureCause,
+};
+use kube::discovery::Scope;
+use std::{collections::BTreeMap, sync::Mutex, time::Duration};
+use tokio::{sync::mpsc::UnboundedSender, time::Instant};
+
+/// How often to check again whether applying objects stalled, when no object
+/// succeeded for long enough but some have yet to fail.
+const STALL_CHECK_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Progress of each object applied by [`apply_objects`](crate::apply_objects).
+pub(crate) struct Progress {
+    state: Mutex<State>,
+    events: Option<UnboundedSender<Event>>,
+}
+
+struct State {
+    started: Instant,
+    last_success: Instant,
+    objects: Vec<ObjectState>,
+}
+
+#[derive(Clone, Debug)]
+pub(crate) struct ObjectState {
+    pub label: String,
+    pub identity: Option<ObjectIdentity>,
+    pub attempts: usize,
+    pub throttled: usize,
+    pub done: bool,
+    /// Time from the start until the object was done.
+    pub duration: Option<Duration>,
+    pub failures: Vec<FailedAttempt>,
+}
+
+impl ObjectState {
+    pub fn last_failure(&self) -> Option<&FailedAttempt> {
+        self.failures.last()
+    }
+}
+
+impl Progress {
+    /// Tracks objects given with their label and identity, if any.
+    pub fn new(
+        objects: impl IntoIterator<Item = (String, Option<ObjectIdentity>)>,
+        events: Option<UnboundedSender<Event>>,
+    ) -> Self {
+        let state = Mutex::new(State {
+            started: Instant::now(),
+            last_success: Instant::now(),
+            objects: objects
+                .into_iter()
+                .map(|(label, identity)| ObjectState {
+                    label,
+                    identity,
+                    attempts: 0,
+                    throttled: 0,
+                    done: false,
+                    duration: None,
+                    failures: vec![],
+                })
+                .collect(),
+        });
+        Self { state, events }
+    }
+
+    pub fn tracker(&self, index: usize) -> Tracker<'_> {
+        Tracker {
+            progress: self,
+            index,
+        }
+    }
+
+    /// Resolves once no object succeeded for `window` while all remaining
+    /// objects failed at least once, i.e. when retrying is unlikely to help.
+    pub async fn stalled(&self, window: Duration) {
+        loop {
+            let last_success = self.state.lock().unwrap().last_success;
+            tokio::time::sleep_until(last_success + window).await;
+
+            let (progressed, all_failed) = {
+                let state = self.state.lock().unwrap();
+                (
+                    state.last_success != last_success,
+                    state
+                        .objects
+                        .iter()
+                        .all(|o| o.done || o.last_failure().is_some()),
+                )
+            };
+            if progressed {
+                continue;
+            }
+            if all_failed {
+                return;
+            }
+            tokio::time::sleep(STALL_CHECK_INTERVAL).await;
+        }
+    }
+
+    /// Returns the time elapsed since the start.
+    pub fn elapsed(&self) -> Duration {
+        self.state.lock().unwrap().started.elapsed()
+    }
+
+    pub fn objects(&self) -> Vec<ObjectState> {
+        self.state.lock().unwrap().objects.clone()
+    }
+
+    /// Returns the objects that are not done yet, grouped by the cause of
+    /// their last failure.
+    pub fn remaining(&self) -> BTreeMap<Option<FailureCause>, Vec<(usize, ObjectState)>> {
+        let mut remaining: BTreeMap<_, Vec<_>> = BTreeMap::new();
+        for (i, o) in self.state.lock().unwrap().objects.iter().enumerate() {
+            if !o.done {
+                let cause = o.last_failure().map(|f| f.cause);
+                remaining.entry(cause).or_default().push((i, o.clone()));
+            }
+        }
+        remaining
+    }
+}
+
+/// Records the progress of a single object.
+pub(crate) struct Tracker<'a> {
+    progress: &'a Progress,
+    index: usize,
+}
+
+impl Tracker<'_> {
+    /// Records the scope of the object's kind once discovered, which drops
+    /// the namespace from its identity if it is cluster-scoped.
+    pub fn discovered(&self, scope: &Scope) {
+        let mut state = self.progress.state.lock().unwrap();
+        let o = &mut state.objects[self.index];
+        if let Some(identity) = &mut o.identity {
+            identity.scope(scope);
+            o.label = identity.to_string();
+        }
+    }
+
+    pub fn succeeded(&self) {
+        let mut state = self.progress.state.lock().unwrap();
+        state.last_success = Instant::now();
+        state.objects[self.index].attempts += 1;
+    }
+
+    pub fn failed(&self, cause: FailureCause, error: &impl ToString) {
+        let mut state = self.progress.state.lock().unwrap();
+        let elapsed = state.started.elapsed();
+        let o = &mut state.objects[self.index];
+        o.attempts += 1;
+        if cause == FailureCause::Throttled {
+            o.throttled += 1;
+        }
+        let error = error.to_string();
+        o.failures.push(FailedAttempt {
+            attempt: o.attempts,
+            elapsed,
+            cause,
+            error: error.clone(),
+        });
+        self.send(&o.label, EventKind::AttemptFailed { cause, error });
+    }
+
+    /// Marks the object as done, whether it succeeded or was given up on
+    /// because of `error`.
+    pub fn finished(&self, error: Option<&ApplyError>) {
+        let mut state = self.progress.state.lock().unwrap();
+        let duration = state.started.elapsed();
+        let o = &mut state.objects[self.index];
+        o.done = true;
+        o.duration = Some(duration);
+        let kind = match error {
+            None => EventKind::Succeeded,
+            Some(e) => EventKind::GaveUp {
+                error: e.to_string(),
+            },
+        };
+        self.send(&o.label, kind);
+    }
+
+    fn send(&self, label: &str, kind: EventKind) {
+        if let Some(events) = &self.progress.events {
+            // The receiver going away only means nobody is interested anymore.
+            let _ = events.send(Event {
+                index: self.index,
+                label: label.to_string(),
+                kind,
+            });
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn progress(count: usize) -> Progress {
+        Progress::new((0..count).map(|i| (format!("pod/{i}"), None)), None)
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn stall_when_all_remaining_objects_failed() {
+        let p = progress(3);
+        let start = Instant::now();
+
+        p.tracker(0).succeeded();
+        p.tracker(0).finished(None);
+        p.tracker(1).failed(FailureCause::MissingKind, &"no kind");
+        p.tracker(2)
+            .failed(FailureCause::MissingNamespace, &"no ns");
+        p.stalled(Duration::from_secs(10)).await;
+        assert_eq!(start.elapsed(), Duration::from_secs(10));
+
+        let remaining = p.remaining();
+        assert_eq!(remaining.len(), 2);
+        assert_eq!(remaining[&Some(FailureCause::MissingKind)][0].0, 1);
+        assert_eq!(remaining[&Some(FailureCause::MissingNamespace)][0].0, 2);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn no_stall_while_objects_succeed() {
+        let p = progress(2);
+        let start = Instant::now();
+
+        p.tracker(1).failed(FailureCause::Other, &"error");
+        tokio::join!(p.stalled(Duration::from_secs(10)), async {
+            tokio::time::sleep(Duration::from_secs(5)).await;
+            p.tracker(0).succeeded();
+            p.tracker(0).finished(None);
+        });
+        assert_eq!(start.elapsed(), Duration::from_secs(15));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn no_stall_while_objects_are_yet_to_fail() {
+        let p = progress(2);
+        let start = Instant::now();
+
+        p.tracker(1).failed(FailureCause::Other, &"error");
+        tokio::join!(p.stalled(Duration::from_secs(10)), async {
+            tokio::time::sleep(Duration::from_millis(11500)).await;
+            p.tracker(0).failed(FailureCause::Other, &"error");
+        });
+        assert_eq!(start.elapsed(), Duration::from_secs(12));
+    }
+}
@@ -0,0 +1,141 @@
+//! Limiting of the rate of requests made to the API server.
+
+use http::Request;
+use std::{
+    future::Future,
+    pin::Pin,
+    sync::{Arc, Mutex},
+    task::{ready, Context, Poll},
+    time::Duration,
+};
+use tokio::time::{Instant, Sleep};
+use tower::{Layer, Service};
+
+/// Limits the rate of requests of a client with a token bucket, like the
+/// `--qps` and `--burst` options of client-go: up to `burst` requests can be
+/// made at once, after which requests are spread out to `qps` per second.
+///
+/// Clones of the layer share the same bucket, so that it can limit several
+/// clients as a whole.
+#[derive(Clone, Debug)]
+pub struct RateLimitLayer {
+    bucket: Arc<Mutex<Bucket>>,
+}
+
+impl RateLimitLayer {
+    /// Creates a layer allowing `qps` requests per second on average, and up
+    /// to `burst` requests at once. A `burst` of 0 is treated as 1.
+    ///
+    /// # Panics
+    /// Panics if `qps` is not strictly positive.
+    pub fn new(qps: f64, burst: u32) -> Self {
+        assert!(qps > 0.0, "qps should be strictly positive");
+        let burst = f64::from(burst.max(1));
+        Self {
+            bucket: Arc::new(Mutex::new(Bucket {
+                qps,
+                burst,
+                tokens: burst,
+                refilled: Instant::now(),
+            })),
+        }
+    }
+}
+
+impl<S> Layer<S> for RateLimitLayer {
+    type Service = RateLimit<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        RateLimit {
+            inner,
+            bucket: Arc::clone(&self.bucket),
+            reserved: false,
+            sleep: None,
+        }
+    }
+}
+
+/// Service created by [`RateLimitLayer`].
+pub struct RateLimit<S> {
+    inner: S,
+    bucket: Arc<Mutex<Bucket>>,
+    /// Whether a token was taken for the next call.
+    reserved: bool,
+    sleep: Option<Pin<Box<Sleep>>>,
+}
+
+impl<S, ReqBody> Service<Request<ReqBody>> for RateLimit<S>
+where
+    S: Service<Request<ReqBody>>,
+{
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = S::Future;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        while !self.reserved {
+            if let Some(sleep) = &mut self.sleep {
+                ready!(sleep.as_mut().poll(cx));
+                self.sleep = None;
+            }
+            match self.bucket.lock().unwrap().take(Instant::now()) {
+                Ok(()) => self.reserved = true,
+                Err(at) => self.sleep = Some(Box::pin(tokio::time::sleep_until(at))),
+            }
+        }
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, req: Request<ReqBody>) -> Self::Future {
+        assert!(self.reserved, "poll_ready should be called before call");
+        self.reserved = false;
+        self.inner.call(req)
+    }
+}
+
+#[derive(Debug)]
+struct Bucket {
+    qps: f64,
+    burst: f64,
+    tokens: f64,
+    refilled: Instant,
+}
+
+impl Bucket {
+    /// Takes a token, or returns when one will be available.
+    fn take(&mut self, now: Instant) -> Result<(), Instant> {
+        let elapsed = now.saturating_duration_since(self.refilled).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * self.qps).min(self.burst);
+        self.refilled = now;
+
+        if self.tokens >= 1.0 {
+            self.tokens -= 1.0;
+            Ok(())
+        } else {
+            Err(now + Duration::from_secs_f64((1.0 - self.tokens) / self.qps))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use http::Response;
+    use tower_test::mock;
+
+    #[tokio::test(start_paused = true)]
+    async fn spread_requests_after_burst() {
+        let (service, mut handle) = mock::pair::<Request<()>, Response<()>>();
+        let mut service = RateLimitLayer::new(2.0, 3).layer(service);
+        handle.allow(10);
+        let start = Instant::now();
+
+        for _ in 0..5 {
+            futures::future::poll_fn(|cx| service.poll_ready(cx))
+                .await
+                .unwrap();
+            drop(service.call(Request::new(())));
+        }
+        assert_eq!(start.elapsed(), Duration::from_secs(1));
+    }
+}
@@ -0,0 +1,368 @@
+//! Redaction of sensitive values, i.e. the data of Secrets and the fields
+//! configured as sensitive, from anything written out.
+
+use crate::overrides::glob_match;
+use base64::{prelude::BASE64_STANDARD, Engine};
+use kube::{api::DynamicObject, core::GroupVersionKind};
+use serde::Deserialize;
+use serde_json::Value;
+use std::{
+    borrow::Cow,
+    sync::{Arc, RwLock},
+};
+
+/// Replaces sensitive values.
+pub const MASK: &str = "***";
+
+/// Values shorter than this are not redacted from text, as they would mask
+/// common words, e.g. `default` or `admin`.
+const MIN_TEXT_LEN: usize = 8;
+
+/// Annotation set by `kubectl apply`, holding a copy of the object.
+const LAST_APPLIED_ANNOTATION: &str = "kubectl.kubernetes.io/last-applied-configuration";
+
+/// Fields holding sensitive values in the objects matching a selector, e.g.
+/// as read from a configuration file:
+///
+/// ```yaml
+/// kind: ConfigMap
+/// paths: [data.password, data.*-token]
+/// ```
+///
+/// Objects are selected like with [`KindOverride`](crate::overrides::KindOverride).
+/// Paths are made of keys separated by dots, each of which can contain `*`
+/// wildcards, and also match the items of arrays.
+#[derive(Clone, Debug, Default, PartialEq, Deserialize)]
+#[serde(rename_all = "camelCase", deny_unknown_fields)]
+pub struct SensitiveFields {
+    pub group: Option<String>,
+    pub version: Option<String>,
+    pub kind: Option<String>,
+    pub paths: Vec<String>,
+}
+
+impl SensitiveFields {
+    fn matches(&self, gvk: &GroupVersionKind) -> bool {
+        let matches = |pattern: &Option<String>, value: &str| {
+            pattern.as_deref().is_none_or(|p| glob_match(p, value))
+        };
+        matches(&self.group, &gvk.group)
+            && matches(&self.version, &gvk.version)
+            && matches(&self.kind, &gvk.kind)
+    }
+}
+
+/// Masks sensitive values in objects, and in any text once learned from the
+/// objects they come from.
+///
+/// Clones share the same fields and learned values, so that a redactor can be
+/// set up before they are known, e.g. to write logs.
+#[derive(Clone, Debug, Default)]
+pub struct Redactor {
+    /// Whether to leave sensitive values as is.
+    disabled: bool,
+    state: Arc<RwLock<State>>,
+}
+
+#[derive(Debug, Default)]
+struct State {
+    fields: Vec<SensitiveFields>,
+    /// Sensitive values to mask in text, longest first.
+    values: Vec<String>,
+}
+
+impl Redactor {
+    /// Creates a redactor masking the data of Secrets, or nothing if
+    /// `show_secrets` is set.
+    pub fn new(show_secrets: bool) -> Self {
+        Self {
+            disabled: show_secrets,
+            ..Default::default()
+        }
+    }
+
+    /// Also masks these fields.
+    pub fn add_fields(&self, fields: impl IntoIterator<Item = SensitiveFields>) {
+        self.state.write().unwrap().fields.extend(fields);
+    }
+
+    /// Remembers the sensitive values of `objects`, to mask them in text.
+    /// Base64 encoded Secret data is remembered decoded too, and values are
+    /// remembered as escaped in logs formatted as JSON or logfmt too.
+    pub fn learn<'a>(&self, objects: impl IntoIterator<Item = &'a DynamicObject>) {
+        self.learn_values(
+            objects
+                .into_iter()
+                .filter_map(|o| serde_json::to_value(o).ok()),
+        );
+    }
+
+    /// Like [`Redactor::learn`], with objects that may not be valid yet, e.g.
+    /// documents of the input as soon as they are read.
+    pub fn learn_values(&self, objects: impl IntoIterator<Item = Value>) {
+        if self.disabled {
+            return;
+        }
+        let mut learned = vec![];
+        for mut value in objects {
+            self.visit(&mut value, &mut |path, v| {
+                if let Value::String(s) = v {
+                    if path == ["data", "*"] {
+                        if let Ok(decoded) = BASE64_STANDARD.decode(s.as_bytes()) {
+                            learned.extend(String::from_utf8(decoded));
+                        }
+                    }
+                    learned.push(s.clone());
+                }
+            });
+        }
+
+        let mut state = self.state.write().unwrap();
+        state.values.extend(
+            learned
+                .iter()
+                .filter(|v| v.len() >= MIN_TEXT_LEN)
+                .flat_map(|v| escaped_forms(v)),
+        );
+        state
+            .values
+            .sort_by(|a, b| b.len().cmp(&a.len()).then_with(|| a.cmp(b)));
+        state.values.dedup();
+    }
+
+    /// Masks the sensitive values of an object.
+    pub fn redact_object(&self, object: &mut Value) {
+        if self.disabled {
+            return;
+        }
+        self.visit(object, &mut |_, v| *v = Value::String(MASK.to_string()));
+    }
+
+    /// Masks the sensitive values learned so far in `text`, where they are
+    /// whole tokens, so that values happening to be part of a word do not mask
+    /// it.
+    pub fn redact_text<'a>(&self, text: &'a str) -> Cow<'a, str> {
+        let state = self.state.read().unwrap();
+        let mut text = Cow::Borrowed(text);
+        for v in &state.values {
+            if let Some(redacted) = replace_tokens(&text, v) {
+                text = Cow::Owned(redacted);
+            }
+        }
+        text
+    }
+
+    /// Masks the sensitive values learned so far in every string of `value`.
+    pub fn redact_strings(&self, value: &mut Value) {
+        match value {
+            Value::String(s) => {
+                if let Cow::Owned(redacted) = self.redact_text(s) {
+                    *s = redacted;
+                }
+            }
+            Value::Array(items) => items.iter_mut().for_each(|v| self.redact_strings(v)),
+            Value::Object(map) => map.values_mut().for_each(|v| self.redact_strings(v)),
+            _ => {}
+        }
+    }
+
+    /// Calls `f` with each sensitive value of `object`, along with the
+    /// pattern of its path.
+    fn visit(&self, object: &mut Value, f: &mut impl FnMut(&[&str], &mut Value)) {
+        let gvk = GroupVersionKind::try_from(&kube::core::TypeMeta {
+            api_version: object["apiVersion"]
+                .as_str()
+                .unwrap_or_default()
+                .to_string(),
+            kind: object["kind"].as_str().unwrap_or_default().to_string(),
+        });
+        let Ok(gvk) = gvk else {
+            return;
+        };
+
+        if gvk.group.is_empty() && gvk.kind == "Secret" {
+            for path in [["data", "*"], ["stringData", "*"]] {
+                visit_path(object, &path, &path, f);
+            }
+            let path = ["metadata", "annotations", LAST_APPLIED_ANNOTATION];
+            visit_path(object, &path, &path, f);
+        }
+        let state = self.state.read().unwrap();
+        for fields in state.fields.iter().filter(|s| s.matches(&gvk)) {
+            for path in &fields.paths {
+                let path: Vec<_> = path.split('.').collect();
+                visit_path(object, &path, &path, f);
+            }
+        }
+    }
+}
+
+/// Calls `f` with the values of `value` at `path`.
+fn visit_path(
+    value: &mut Value,
+    path: &[&str],
+    pattern: &[&str],
+    f: &mut impl FnMut(&[&str], &mut Value),
+) {
+    let Some((key, rest)) = path.split_first() else {
+        if !value.is_null() {
+            f(pattern, value);
+        }
+        return;
+    };
+    match value {
+        Value::Object(map) => {
+            for (_, v) in map.iter_mut().filter(|(k, _)| glob_match(key, k)) {
+                visit_path(v, rest, pattern, f);
+            }
+        }
+        Value::Array(items) if *key == "*" => {
+            for v in items {
+                visit_path(v, rest, pattern, f);
+            }
+        }
+        // A value where sensitive ones are expected within, as in an invalid
+        // object, may be one of them, e.g. Secret data given as a string.
+        Value::String(_) if path.len() < pattern.len() => f(pattern, value),
+        _ => {}
+    }
+}
+
+/// Returns `value`, along with how it is escaped in JSON strings and by
+/// `Debug`, and by both as for `Debug` fields of JSON logs.
+fn escaped_forms(value: &str) -> Vec<String> {
+    let json = |s: &str| {
+        let quoted = serde_json::to_string(s).unwrap_or_default();
+        quoted[1..quoted.len() - 1].to_string()
+    };
+    let debug = value.escape_debug().to_string();
+    let mut forms = vec![json(&debug), json(value), debug, value.to_string()];
+    forms.dedup();
+    forms
+}
+
+/// Replaces the occurrences of `value` in `text` that are not part of a
+/// longer word, or returns `None` if there are none.
+fn replace_tokens(text: &str, value: &str) -> Option<String> {
+    let is_word = |c: Option<char>| c.is_some_and(|c| c.is_alphanumeric() || c == '_');
+    let (first, last) = (value.chars().next(), value.chars().next_back());
+
+    let mut redacted = String::new();
+    let mut copied = 0;
+    for (start, _) in text.match_indices(value) {
+        let end = start + value.len();
+        let before = &text[..start];
+        // Escape sequences like `\n` also end words.
+        let escaped = before.chars().nth_back(1) == Some('\\');
+        let starts_token = !is_word(first) || !is_word(before.chars().next_back()) || escaped;
+        let ends_token = !is_word(last) || !is_word(text[end..].chars().next());
+        if starts_token && ends_token {
+            redacted.push_str(&text[copied..start]);
+            redacted.push_str(MASK);
+            copied = end;
+        }
+    }
+    if copied == 0 {
+        return None;
+    }
+    redacted.push_str(&text[copied..]);
+    Some(redacted)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn secret() -> Value {
+        json!({
+            "apiVersion": "v1",
+            "kind": "Secret",
+            "metadata": { "name": "example" },
+            "data": { "password": "Y29ycmVjdC1ob3JzZQ==" },
+            "stringData": { "token": "s3cr3t-t0ken" },
+        })
+    }
+
+    #[test]
+    fn redact_secrets() {
+        let redactor = Redactor::new(false);
+        let mut object = secret();
+        redactor.redact_object(&mut object);
+        assert_eq!(object["data"]["password"], MASK);
+        assert_eq!(object["stringData"]["token"], MASK);
+        assert_eq!(object["metadata"]["name"], "example");
+
+        redactor.learn([&serde_json::from_value(secret()).unwrap()]);
+        assert_eq!(
+            redactor.redact_text("invalid value correct-horse or s3cr3t-t0ken"),
+            "invalid value *** or ***"
+        );
+        let mut report = json!({ "errors": ["correct-horse is invalid"] });
+        redactor.redact_strings(&mut report);
+        assert_eq!(report, json!({ "errors": ["*** is invalid"] }));
+    }
+
+    #[test]
+    fn redact_whole_tokens_only() {
+        let redactor = Redactor::new(false);
+        redactor.learn_values([json!({
+            "apiVersion": "v1",
+            "kind": "Secret",
+            "stringData": { "user": "admin", "namespace": "platform", "token": "s3cr3t-t0ken" },
+        })]);
+        assert_eq!(
+            redactor.redact_text("admin of the platform-team in platforms: platform"),
+            "admin of the ***-team in platforms: ***"
+        );
+        assert_eq!(
+            redactor.redact_text(r#"{"token":"s3cr3t-t0ken\ns3cr3t-t0ken"}"#),
+            r#"{"token":"***\n***"}"#
+        );
+    }
+
+    #[test]
+    fn learn_from_invalid_objects() {
+        let redactor = Redactor::new(false);
+        redactor.learn_values([json!({
+            "apiVersion": "v1",
+            "kind": "Secret",
+            "data": "not-a-map-of-data",
+        })]);
+        assert_eq!(
+            redactor.redact_text(r#"invalid type: string "not-a-map-of-data""#),
+            r#"invalid type: string "***""#
+        );
+    }
+
+    #[test]
+    fn redact_configured_fields() {
+        let redactor = Redactor::new(false);
+        redactor.add_fields([SensitiveFields {
+            kind: Some("Deployment".to_string()),
+            paths: vec!["spec.template.spec.containers.*.env.*.value".to_string()],
+            ..Default::default()
+        }]);
+        let mut object = json!({
+            "apiVersion": "apps/v1",
+            "kind": "Deployment",
+            "metadata": { "name": "example" },
+            "spec": { "template": { "spec": { "containers": [
+                { "name": "example", "env": [{ "name": "TOKEN", "value": "t0ken" }] },
+            ] } } },
+        });
+        redactor.redact_object(&mut object);
+        assert_eq!(
+            object["spec"]["template"]["spec"]["containers"][0]["env"][0],
+            json!({ "name": "TOKEN", "value": MASK })
+        );
+    }
+
+    #[test]
+    fn show_secrets() {
+        let redactor = Redactor::new(true);
+        let mut object = secret();
+        redactor.redact_object(&mut object);
+        assert_eq!(object, secret());
+    }
+}
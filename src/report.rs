@@ -0,0 +1,81 @@
+//! Outcome of applying a set of objects.
+
+use crate::{analysis::ObjectIdentity, Action, ApplyError, FailureCause};
+use kube::api::DynamicObject;
+use std::time::Duration;
+
+/// What happened to each object given to [`apply_objects`](crate::apply_objects),
+/// in the order given.
+#[derive(Debug, Default)]
+pub struct Report {
+    pub objects: Vec<ObjectReport>,
+}
+
+impl Report {
+    /// Returns the errors of the objects that were given up on.
+    pub fn errors(&self) -> impl Iterator<Item = &ApplyError> {
+        self.objects.iter().filter_map(|o| o.error.as_ref())
+    }
+
+    pub fn is_success(&self) -> bool {
+        self.errors().next().is_none()
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct ObjectReport {
+    /// Identifies the object for humans, e.g. `deployment.apps/foo (namespace bar)`.
+    pub label: String,
+    /// Identifies the object, unless its `apiVersion` cannot be parsed.
+    pub identity: Option<ObjectIdentity>,
+    /// What was done with the object, unless its action annotation is
+    /// invalid.
+    pub action: Option<Action>,
+    /// Number of attempts made at applying or deleting the object.
+    pub attempts: usize,
+    /// Number of those attempts the API server throttled.
+    pub throttled: usize,
+    /// Attempts that failed, in order.
+    pub failures: Vec<FailedAttempt>,
+    /// Time from the start until the object was done with, or given up on.
+    pub duration: Duration,
+    /// The error the object was given up on, if it was.
+    pub error: Option<ApplyError>,
+    /// The object as persisted by the API server once applied, with defaults
+    /// and generated fields, if kept with
+    /// [`ApplyParams::keep_applied`](crate::ApplyParams::keep_applied).
+    pub applied: Option<DynamicObject>,
+}
+
+/// An attempt at applying or deleting an object that failed.
+#[derive(Clone, Debug)]
+pub struct FailedAttempt {
+    /// Number of the attempt, starting at 1.
+    pub attempt: usize,
+    /// Time from the start until the attempt failed.
+    pub elapsed: Duration,
+    pub cause: FailureCause,
+    pub error: String,
+}
+
+/// Something that happened to an object given to
+/// [`apply_objects`](crate::apply_objects).
+#[derive(Clone, Debug)]
+pub struct Event {
+    /// Position of the object among those given to
+    /// [`apply_objects`](crate::apply_objects), starting at 0.
+    pub index: usize,
+    /// Identifies the object for humans, like [`ObjectReport::label`].
+    pub label: String,
+    pub kind: EventKind,
+}
+
+#[derive(Clone, Debug)]
+pub enum EventKind {
+    /// An attempt failed, the object may be retried.
+    AttemptFailed { cause: FailureCause, error: String },
+    /// The object was applied or deleted.
+    Succeeded,
+    /// The object was given up on.
+    GaveUp { error: String },
+}
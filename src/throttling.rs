@@ -0,0 +1,283 @@
+//! Honouring of the delays the API server asks clients to wait for when
+//! throttling them, e.g. with API Priority and Fairness.
+
+use futures::future::BoxFuture;
+use http::{header::RETRY_AFTER, HeaderMap, Request, Response, StatusCode};
+use http_body::Body;
+use http_body_util::BodyExt;
+use kube::client::DynBody;
+use std::{
+    future::Future,
+    pin::Pin,
+    sync::{Arc, Mutex},
+    task::{ready, Context, Poll},
+    time::Duration,
+};
+use tokio::time::{Instant, Sleep};
+use tower::{Layer, Service};
+use tracing::warn;
+
+/// Longest pause honoured, whatever the API server asks for.
+pub const MAX_DELAY: Duration = Duration::from_secs(60);
+
+/// Pauses all requests of a client for as long as the API server asked to in
+/// its last `429 Too Many Requests` response, up to [`MAX_DELAY`]. The delay
+/// is read from the `Retry-After` header, or else from the `retryAfterSeconds`
+/// detail of the Status in the body.
+///
+/// Meant to be added to a [`ClientBuilder`](kube::client::ClientBuilder), so
+/// that retries and discovery made through the client all wait at least as
+/// long as asked, whatever their backoff. Clones share the same pause.
+#[derive(Clone, Debug, Default)]
+pub struct RetryAfterLayer {
+    paused_until: Arc<Mutex<Option<Instant>>>,
+}
+
+impl RetryAfterLayer {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// How long requests remain paused for, if at all.
+    pub fn remaining(&self) -> Option<Duration> {
+        let paused_until = (*self.paused_until.lock().unwrap())?;
+        let remaining = paused_until.saturating_duration_since(Instant::now());
+        (!remaining.is_zero()).then_some(remaining)
+    }
+}
+
+impl<S> Layer<S> for RetryAfterLayer {
+    type Service = RetryAfter<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        RetryAfter {
+            inner,
+            paused_until: Arc::clone(&self.paused_until),
+            sleep: None,
+        }
+    }
+}
+
+/// Service created by [`RetryAfterLayer`].
+pub struct RetryAfter<S> {
+    inner: S,
+    paused_until: Arc<Mutex<Option<Instant>>>,
+    sleep: Option<Pin<Box<Sleep>>>,
+}
+
+impl<S, ReqBody, ResBody> Service<Request<ReqBody>> for RetryAfter<S>
+where
+    S: Service<Request<ReqBody>, Response = Response<ResBody>>,
+    S::Future: Send + 'static,
+    ResBody: Body + FromBuffered + Send + 'static,
+    ResBody::Data: Send,
+    ResBody::Error: std::fmt::Display,
+{
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = BoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        loop {
+            let paused_until = *self.paused_until.lock().unwrap();
+            match paused_until {
+                Some(until) if until > Instant::now() => {
+                    let sleep = self
+                        .sleep
+                        .get_or_insert_with(|| Box::pin(tokio::time::sleep_until(until)));
+                    if sleep.deadline() != until {
+                        sleep.as_mut().reset(until);
+                    }
+                    ready!(sleep.as_mut().poll(cx));
+                }
+                _ => {
+                    self.sleep = None;
+                    return self.inner.poll_ready(cx);
+                }
+            }
+        }
+    }
+
+    fn call(&mut self, req: Request<ReqBody>) -> Self::Future {
+        let paused_until = Arc::clone(&self.paused_until);
+        let resp = self.inner.call(req);
+        Box::pin(async move {
+            let resp = resp.await?;
+            if resp.status() != StatusCode::TOO_MANY_REQUESTS {
+                return Ok(resp);
+            }
+            let (delay, resp) = match retry_after(resp.headers()) {
+                Some(delay) => (Some(delay), resp),
+                None => retry_after_detail(resp).await,
+            };
+            match delay {
+                Some(delay) => {
+                    let delay = delay.min(MAX_DELAY);
+                    warn!(?delay, "Throttled by the API server, pausing requests");
+                    let until = Instant::now() + delay;
+                    let mut paused_until = paused_until.lock().unwrap();
+                    *paused_until = (*paused_until).max(Some(until));
+                }
+                None => warn!("Throttled by the API server"),
+            }
+            Ok(resp)
+        })
+    }
+}
+
+/// Parses the `Retry-After` header, ignoring the rarely used HTTP-date form.
+fn retry_after(headers: &HeaderMap) -> Option<Duration> {
+    let secs = headers
+        .get(RETRY_AFTER)?
+        .to_str()
+        .ok()?
+        .trim()
+        .parse()
+        .ok()?;
+    Some(Duration::from_secs(secs))
+}
+
+/// Reads the `retryAfterSeconds` detail of the Status in the body of a
+/// response, which is buffered to be handed back as is.
+async fn retry_after_detail<B>(resp: Response<B>) -> (Option<Duration>, Response<B>)
+where
+    B: Body + FromBuffered,
+    B::Error: std::fmt::Display,
+{
+    let (parts, body) = resp.into_parts();
+    let bytes = match body.collect().await {
+        Ok(collected) => collected.to_bytes().to_vec(),
+        Err(e) => {
+            warn!(error = %e, "Failed to read throttled response");
+            vec![]
+        }
+    };
+    let delay = serde_json::from_slice::<serde_json::Value>(&bytes)
+        .ok()
+        .and_then(|status| status["details"]["retryAfterSeconds"].as_u64())
+        .map(Duration::from_secs);
+    (delay, Response::from_parts(parts, B::from_buffered(bytes)))
+}
+
+/// Response bodies that can be rebuilt from their content, once read to find
+/// the delay asked for.
+pub trait FromBuffered {
+    fn from_buffered(bytes: Vec<u8>) -> Self;
+}
+
+impl FromBuffered for kube::client::Body {
+    fn from_buffered(bytes: Vec<u8>) -> Self {
+        bytes.into()
+    }
+}
+
+impl FromBuffered for Box<DynBody> {
+    fn from_buffered(bytes: Vec<u8>) -> Self {
+        Box::new(kube::client::Body::from(bytes).map_err(Into::into))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock::with_mock_service;
+    use kube::{client::Body, Client};
+    use serde_json::json;
+
+    fn version_request() -> Request<Body> {
+        Request::get("/version").body(Body::empty()).unwrap()
+    }
+
+    #[test_log::test(tokio::test(start_paused = true))]
+    #[test_log(default_log_filter = "deka=trace")]
+    async fn pause_requests_after_throttling() {
+        let throttled = json!({
+            "kind": "Status",
+            "apiVersion": "v1",
+            "metadata": {},
+            "status": "Failure",
+            "message": "Too many requests, please try again later.",
+            "reason": "TooManyRequests",
+            "details": { "retryAfterSeconds": 5 },
+            "code": 429
+        });
+        let version = json!({
+            "major": "1",
+            "minor": "31",
+            "gitVersion": "v1.31.0",
+            "gitCommit": "",
+            "gitTreeState": "clean",
+            "buildDate": "",
+            "goVersion": "go1.22.5",
+            "compiler": "gc",
+            "platform": "linux/amd64"
+        });
+        let expectations = vec![
+            (
+                version_request(),
+                Response::builder()
+                    .status(StatusCode::TOO_MANY_REQUESTS)
+                    .header(RETRY_AFTER, "5")
+                    .body(Body::from(serde_json::to_vec(&throttled).unwrap()))
+                    .unwrap(),
+            ),
+            (
+                version_request(),
+                Response::builder()
+                    .body(Body::from(serde_json::to_vec(&version).unwrap()))
+                    .unwrap(),
+            ),
+        ];
+
+        with_mock_service(expectations, |s| async {
+            let client = Client::new(RetryAfterLayer::new().layer(s), "default");
+            let start = Instant::now();
+
+            client.apiserver_version().await.unwrap_err();
+            assert_eq!(start.elapsed(), Duration::ZERO);
+            client.apiserver_version().await.unwrap();
+            assert_eq!(start.elapsed(), Duration::from_secs(5));
+        })
+        .await;
+    }
+
+    #[test_log::test(tokio::test(start_paused = true))]
+    #[test_log(default_log_filter = "deka=trace")]
+    async fn pause_for_status_detail_up_to_max_delay() {
+        let throttled = json!({
+            "kind": "Status",
+            "apiVersion": "v1",
+            "metadata": {},
+            "status": "Failure",
+            "message": "Too many requests, please try again later.",
+            "reason": "TooManyRequests",
+            "details": { "retryAfterSeconds": 3600 },
+            "code": 429
+        });
+        let expectations = vec![(
+            version_request(),
+            Response::builder()
+                .status(StatusCode::TOO_MANY_REQUESTS)
+                .body(Body::from(serde_json::to_vec(&throttled).unwrap()))
+                .unwrap(),
+        )];
+
+        with_mock_service(expectations, |s| async {
+            let layer = RetryAfterLayer::new();
+            let client = Client::new(layer.layer(s), "default");
+
+            let err = client.apiserver_version().await.unwrap_err();
+            assert!(matches!(err, kube::Error::Api(r) if r.reason == "TooManyRequests"));
+            assert_eq!(layer.remaining(), Some(MAX_DELAY));
+        })
+        .await;
+    }
+
+    #[test]
+    fn parse_retry_after() {
+        let headers = |v: &str| HeaderMap::from_iter([(RETRY_AFTER, v.parse().unwrap())]);
+        assert_eq!(retry_after(&headers("3")), Some(Duration::from_secs(3)));
+        assert_eq!(retry_after(&headers("Wed, 21 Oct 2015 07:28:00 GMT")), None);
+        assert_eq!(retry_after(&HeaderMap::new()), None);
+    }
+}